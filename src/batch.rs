@@ -0,0 +1,99 @@
+use crate::{env_var, RetryPolicy};
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Overrides [`BatchConfig::interval`], in milliseconds.
+pub const FLUSH_INTERVAL_VAR: &str = "GOSSIP_FLUSH_MS";
+
+/// Overrides [`BatchConfig::max_size`].
+pub const BATCH_SIZE_VAR: &str = "GOSSIP_BATCH_SIZE";
+
+/// How a batch that got no reply is resent. The first resend waits a second,
+/// several round trips even at Maelstrom's 100ms latency, so a batch that is
+/// merely slow to be acknowledged is not sent twice.
+pub const BATCH_RETRY: RetryPolicy =
+    RetryPolicy::exponential(Duration::from_secs(1), Duration::from_secs(4));
+
+/// When a [`Batcher`] sends what it has collected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchConfig {
+    /// How often everything queued is flushed. Nodes register a timer with
+    /// this period and call [`Batcher::flush`] on every tick.
+    pub interval: Duration,
+    /// A destination's batch is flushed as soon as it holds this many
+    /// items, without waiting for the interval.
+    pub max_size: usize,
+}
+
+impl BatchConfig {
+    /// `defaults`, with whatever [`FLUSH_INTERVAL_VAR`] and
+    /// [`BATCH_SIZE_VAR`] set instead. A shorter interval or a smaller
+    /// batch lowers latency at the cost of more messages per operation.
+    pub fn from_env(defaults: BatchConfig) -> anyhow::Result<Self> {
+        let mut config = defaults;
+        if let Some(interval) = env_var(FLUSH_INTERVAL_VAR, number)? {
+            anyhow::ensure!(interval > 0, "`{FLUSH_INTERVAL_VAR}` must be positive");
+            config.interval = Duration::from_millis(interval);
+        }
+        if let Some(max_size) = env_var(BATCH_SIZE_VAR, number)? {
+            anyhow::ensure!(max_size > 0, "`{BATCH_SIZE_VAR}` must be positive");
+            config.max_size = max_size as usize;
+        }
+        Ok(config)
+    }
+}
+
+fn number(value: &str) -> anyhow::Result<u64> {
+    value
+        .parse()
+        .with_context(|| format!("expected a number, got `{value}`"))
+}
+
+/// Coalesces outgoing items per destination, so a node sends one message
+/// carrying many items instead of one message per item.
+#[derive(Debug, Clone)]
+pub struct Batcher<V> {
+    config: BatchConfig,
+    queues: BTreeMap<String, Vec<V>>,
+}
+
+impl<V> Batcher<V> {
+    pub fn new(config: BatchConfig) -> Self {
+        Batcher {
+            config,
+            queues: BTreeMap::new(),
+        }
+    }
+
+    /// Queues `item` for `dest`.
+    pub fn push(&mut self, dest: &str, item: V) {
+        self.queues.entry(dest.to_string()).or_default().push(item);
+    }
+
+    /// Takes the batches that reached [`BatchConfig::max_size`], ordered by
+    /// destination. Nodes call this after handling an event, so a full
+    /// batch goes out without waiting for the next flush.
+    pub fn full(&mut self) -> Vec<(String, Vec<V>)> {
+        let full: Vec<String> = self
+            .queues
+            .iter()
+            .filter(|(_, batch)| batch.len() >= self.config.max_size)
+            .map(|(dest, _)| dest.clone())
+            .collect();
+        full.into_iter()
+            .filter_map(|dest| {
+                let batch = self.queues.remove(&dest)?;
+                Some((dest, batch))
+            })
+            .collect()
+    }
+
+    /// Takes every non-empty batch, ordered by destination.
+    pub fn flush(&mut self) -> Vec<(String, Vec<V>)> {
+        std::mem::take(&mut self.queues)
+            .into_iter()
+            .filter(|(_, batch)| !batch.is_empty())
+            .collect()
+    }
+}
@@ -1,126 +1,379 @@
-use anyhow;
-use anyhow::Context;
+use rust_gosssip_gloomers::kv::{KvClient, KvError, Service};
+use rust_gosssip_gloomers::*;
 use serde::{Deserialize, Serialize};
-use serde_json;
-use std::io::{StdoutLock, Write};
+use std::collections::VecDeque;
+use std::time::{Duration, UNIX_EPOCH};
+use uuid::{Builder, Uuid};
 
-#[derive(Serialize, Deserialize)]
-struct Message {
-    src: String,
-    dest: String,
-    body: Body,
-}
+/// How many counter values a node reserves in `lin-kv` at a time. A crash
+/// wastes at most this many.
+const BLOCK_SIZE: usize = 1000;
 
-#[derive(Serialize, Deserialize)]
-struct Body {
-    #[serde(flatten)]
-    payload: Payload,
-    msg_id: Option<usize>,
-    in_reply_to: Option<usize>,
-}
+/// How long a reservation may take before it is tried again.
+const KV_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a node backs off before retrying a reservation that failed.
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Picks the [`Strategy`]: `memory` (the default), `counter`, `snowflake`,
+/// `uuid` or `uuidv7`.
+const STRATEGY_VAR: &str = "ID_STRATEGY";
 
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 enum Payload {
     Generate,
-    GenerateOk {
-        id: usize,
-    },
-    Init {
-        node_id: String,
-        node_ids: Vec<String>,
-    },
-    InitOk,
+    GenerateOk { id: Id },
+}
+
+#[derive(Clone)]
+enum Tick {
+    Retry,
+}
+
+/// Counters and snowflakes are numbers, UUIDs are strings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Id {
+    Number(u64),
+    Text(String),
+}
+
+/// How the node makes ids, chosen with [`STRATEGY_VAR`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Strategy {
+    /// `counter * node_ids.len() + delta`, reserved in `lin-kv`. It is the
+    /// only counter that stays unique when nodes restart, at the cost of a
+    /// round trip to `lin-kv` every [`BLOCK_SIZE`] ids.
+    Counter,
+    /// The same counter kept only in memory, the default: as fast as it gets,
+    /// but with duplicates after a restart.
+    Memory,
+    Snowflake,
+    UuidV4,
+    UuidV7,
+}
+
+impl Strategy {
+    fn parse(name: &str) -> anyhow::Result<Self> {
+        Ok(match name {
+            "counter" => Strategy::Counter,
+            "memory" => Strategy::Memory,
+            "snowflake" => Strategy::Snowflake,
+            "uuid" => Strategy::UuidV4,
+            "uuidv7" => Strategy::UuidV7,
+            _ => anyhow::bail!(
+                "unknown id strategy `{name}`, expected counter, memory, snowflake, uuid or uuidv7"
+            ),
+        })
+    }
+
+    fn from_env() -> anyhow::Result<Self> {
+        Ok(env_var(STRATEGY_VAR, Self::parse)?.unwrap_or(Strategy::Memory))
+    }
+}
+
+/// Makes ids that are unique without talking to anyone.
+trait IdStrategy {
+    /// The next id, given the wall clock in milliseconds since the Unix
+    /// epoch.
+    fn generate(&mut self, now: u64) -> Id;
+}
+
+/// `next * stride + delta`, where `stride` is the number of nodes.
+struct MemoryCounter {
+    next: u64,
+    stride: u64,
+    delta: u64,
+}
+
+impl IdStrategy for MemoryCounter {
+    fn generate(&mut self, _now: u64) -> Id {
+        let id = self.next * self.stride + self.delta;
+        self.next += 1;
+        Id::Number(id)
+    }
+}
+
+/// Twitter's layout: 41 bits of milliseconds since [`Snowflake::EPOCH`], 10
+/// bits of node index and 12 bits of sequence within the millisecond.
+struct Snowflake {
+    node: u64,
+    last: u64,
+    sequence: u64,
+}
+
+impl Snowflake {
+    /// 2024-01-01T00:00:00Z, in milliseconds since the Unix epoch.
+    const EPOCH: u64 = 1_704_067_200_000;
+    const NODE_BITS: u32 = 10;
+    const SEQUENCE_BITS: u32 = 12;
+
+    fn new(node: usize) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            node < 1 << Self::NODE_BITS,
+            "snowflakes only have room for {} nodes",
+            1 << Self::NODE_BITS
+        );
+        Ok(Snowflake {
+            node: node as u64,
+            last: 0,
+            sequence: 0,
+        })
+    }
+}
+
+impl IdStrategy for Snowflake {
+    /// Timestamps never go backwards, even if the clock does. Once a
+    /// millisecond's sequence runs out the node borrows the next millisecond
+    /// rather than waiting for it.
+    fn generate(&mut self, now: u64) -> Id {
+        let now = now.saturating_sub(Self::EPOCH);
+        if now > self.last {
+            self.last = now;
+            self.sequence = 0;
+        } else {
+            self.sequence += 1;
+            if self.sequence == 1 << Self::SEQUENCE_BITS {
+                self.last += 1;
+                self.sequence = 0;
+            }
+        }
+        Id::Number(
+            self.last << (Self::NODE_BITS + Self::SEQUENCE_BITS)
+                | self.node << Self::SEQUENCE_BITS
+                | self.sequence,
+        )
+    }
+}
+
+/// Random UUIDs; 122 random bits make a collision vanishingly unlikely.
+struct UuidV4;
+
+impl IdStrategy for UuidV4 {
+    fn generate(&mut self, _now: u64) -> Id {
+        Id::Text(Uuid::new_v4().to_string())
+    }
+}
+
+/// UUIDs that sort by the millisecond they were made in, followed by 74
+/// random bits.
+struct UuidV7 {
+    last: u64,
+}
+
+impl IdStrategy for UuidV7 {
+    fn generate(&mut self, now: u64) -> Id {
+        self.last = self.last.max(now);
+        let random = Uuid::new_v4().into_bytes();
+        let random: &[u8; 10] = random[..10].try_into().expect("uuids have 16 bytes");
+        Id::Text(
+            Builder::from_unix_timestamp_millis(self.last, random)
+                .into_uuid()
+                .to_string(),
+        )
+    }
 }
 
 struct UniqueIdNode {
+    /// Set unless the node hands out counters.
+    strategy: Option<Box<dyn IdStrategy>>,
+    /// The counter only lives in memory, so the node durably reserves blocks
+    /// of it in `lin-kv` before using them: after a restart it carries on
+    /// past everything its previous incarnation may have handed out.
     counter: usize,
-    delta: Option<usize>,
-    node_id: Option<String>,
-    node_ids: Option<Vec<String>>,
+    /// Counter values below this one are reserved for this node.
+    reserved: usize,
+    reserving: bool,
+    /// Set when a reservation failed and waits for the next [`Tick::Retry`].
+    retry: bool,
+    /// Requests waiting for a reservation.
+    waiting: VecDeque<Message<Payload>>,
+    delta: usize,
+    node_id: String,
+    node_ids: Vec<String>,
+    kv: KvClient,
 }
 
-impl UniqueIdNode {
-    pub fn new(node_id: Option<String>, node_ids: Option<Vec<String>>) -> UniqueIdNode {
-        UniqueIdNode {
+impl Node<Strategy, Payload, Tick> for UniqueIdNode {
+    fn from_init(
+        strategy: Strategy,
+        init: Init,
+        scheduler: &mut Scheduler<Payload, Tick>,
+    ) -> anyhow::Result<Self> {
+        let mut node_ids = init.node_ids;
+        node_ids.sort();
+        let delta = node_ids
+            .iter()
+            .position(|x| *x == init.node_id)
+            .ok_or_else(|| anyhow::anyhow!("node_id not present in node_ids"))?;
+        let strategy: Option<Box<dyn IdStrategy>> = match strategy {
+            Strategy::Counter => {
+                scheduler.every(RETRY_INTERVAL, Tick::Retry);
+                None
+            }
+            Strategy::Memory => Some(Box::new(MemoryCounter {
+                next: 0,
+                stride: node_ids.len() as u64,
+                delta: delta as u64,
+            })),
+            Strategy::Snowflake => Some(Box::new(Snowflake::new(delta)?)),
+            Strategy::UuidV4 => Some(Box::new(UuidV4)),
+            Strategy::UuidV7 => Some(Box::new(UuidV7 { last: 0 })),
+        };
+
+        Ok(UniqueIdNode {
+            strategy,
             counter: 0,
-            node_id,
+            reserved: 0,
+            reserving: false,
+            retry: false,
+            waiting: VecDeque::new(),
+            delta,
+            node_id: init.node_id,
             node_ids,
-            delta: None,
-        }
+            kv: KvClient::new(Service::LinKv).with_timeout(KV_TIMEOUT),
+        })
     }
-}
 
-impl UniqueIdNode {
-    pub fn generate(&mut self, input: Message, output: &mut StdoutLock) -> anyhow::Result<()> {
+    fn step(
+        &mut self,
+        input: Event<Payload, Tick>,
+        output: &mut Output<Self>,
+    ) -> anyhow::Result<()> {
+        let input = match input {
+            Event::Message(input) => input,
+            Event::Tick(Tick::Retry) if self.retry => {
+                self.retry = false;
+                return self.reserve(output);
+            }
+            Event::Tick(Tick::Retry) | Event::Eof => return Ok(()),
+        };
         match input.body.payload {
             Payload::Generate => {
-                let id = self.counter * self.node_ids.as_ref().unwrap().iter().len()
-                    + self.delta.unwrap();
-                self.counter += 1;
-                let response = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: Body {
-                        payload: Payload::GenerateOk { id },
-                        msg_id: input.body.msg_id,
-                        in_reply_to: input.body.msg_id,
-                    },
-                };
-
-                serde_json::to_writer(&mut *output, &response)?;
-                output.write_all(b"\n")?;
-
-                Ok(())
+                if let Some(strategy) = &mut self.strategy {
+                    let now = output
+                        .system_time()
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |elapsed| elapsed.as_millis() as u64);
+                    let id = strategy.generate(now);
+                    return output.send(&input.reply(Payload::GenerateOk { id }, output));
+                }
+                self.waiting.push_back(input);
+                self.serve(output)
             }
             Payload::GenerateOk { .. } => Ok(()),
-            Payload::Init {
-                node_id,
-                mut node_ids,
-            } => {
-                node_ids.sort();
-                if let Some(delta) = node_ids.iter().position(|x| *x == node_id) {
-                    self.delta = Some(delta);
-                } else {
-                    panic!("node_id not present in node_ids");
-                }
-
-                self.node_ids = Some(node_ids);
+        }
+    }
+}
 
-                let response = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: Body {
-                        payload: Payload::InitOk,
-                        msg_id: input.body.msg_id,
-                        in_reply_to: input.body.msg_id,
-                    },
-                };
+impl UniqueIdNode {
+    /// Answers waiting requests for as long as the reservation lasts, and
+    /// reserves the next block once it runs out.
+    fn serve(&mut self, output: &mut Output<Self>) -> anyhow::Result<()> {
+        while self.counter < self.reserved {
+            let Some(request) = self.waiting.pop_front() else {
+                break;
+            };
+            let id = Id::Number((self.counter * self.node_ids.len() + self.delta) as u64);
+            self.counter += 1;
+            output.send(&request.reply(Payload::GenerateOk { id }, output))?;
+        }
+        if !self.waiting.is_empty() && !self.reserving {
+            self.reserving = true;
+            self.reserve(output)?;
+        }
+        Ok(())
+    }
 
-                serde_json::to_writer(&mut *output, &response).expect("Can not serialize");
-                output
-                    .write_all(b"\n")
-                    .context("writing trailing new line")?;
+    /// Moves the node's high-water mark in `lin-kv` from `reserved` up by a
+    /// block. If it is somewhere else, an earlier incarnation got there
+    /// first: the node skips past it and tries again. Any other failure is
+    /// retried on the next [`Tick::Retry`].
+    fn reserve(&mut self, output: &mut Output<Self>) -> anyhow::Result<()> {
+        let from = self.reserved;
+        self.kv.cas(
+            output,
+            self.key(),
+            from,
+            from + BLOCK_SIZE,
+            true,
+            move |this: &mut Self, reply, output| match reply {
+                Ok(()) => {
+                    this.reserved = from + BLOCK_SIZE;
+                    this.reserving = false;
+                    this.serve(output)
+                }
+                Err(KvError::PreconditionFailed) => {
+                    this.kv
+                        .read(output, this.key(), |this: &mut Self, reply, output| {
+                            let Ok(mark) = reply else {
+                                this.retry = true;
+                                return Ok(());
+                            };
+                            this.counter = this.counter.max(mark);
+                            this.reserved = this.reserved.max(mark);
+                            this.reserve(output)
+                        })
+                }
+                // A timed out `cas` may still have gone through, in which
+                // case the next attempt finds the mark moved.
+                Err(_) => {
+                    this.retry = true;
+                    Ok(())
+                }
+            },
+        )
+    }
 
-                Ok(())
-            }
-            Payload::InitOk => Ok(()),
-        }
+    fn key(&self) -> String {
+        format!("unique-ids/{}", self.node_id)
     }
 }
 
 fn main() -> anyhow::Result<()> {
-    let mut node = UniqueIdNode::new(None, None);
-    let stdin = std::io::stdin().lock();
-    let mut stdout = std::io::stdout().lock();
-
-    let inputs = serde_json::Deserializer::from_reader(stdin).into_iter::<Message>();
-    for input in inputs {
-        let input: Message =
-            input.context("Maelstrom input from STDIN can not be deserialized ")?;
-        node.generate(input, &mut stdout)?;
+    run::<_, UniqueIdNode, _, _>(Strategy::from_env()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_gosssip_gloomers::checker::{self, Verdict};
+    use rust_gosssip_gloomers::sim::{self, workload::WorkloadKind, SimConfig};
+
+    fn simulate(strategy: Strategy, kill: bool) -> anyhow::Result<Verdict> {
+        let mut config = SimConfig {
+            seed: 11,
+            ..SimConfig::new(WorkloadKind::UniqueIds)
+        };
+        config.nemesis.kill = kill;
+        config.nemesis.interval = Duration::from_secs(2);
+        let (history, _) = sim::simulate_run::<_, UniqueIdNode, _, _>(strategy, &config)?;
+        Ok(checker::check(config.workload, &history).expect("unique-ids has a checker"))
     }
 
-    Ok(())
+    #[test]
+    fn every_strategy_hands_out_unique_ids() -> anyhow::Result<()> {
+        for strategy in [
+            Strategy::Counter,
+            Strategy::Memory,
+            Strategy::Snowflake,
+            Strategy::UuidV4,
+            Strategy::UuidV7,
+        ] {
+            let verdict = simulate(strategy, false)?;
+            assert!(verdict.valid, "{strategy:?}: {verdict}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reserved_counters_survive_restarts() -> anyhow::Result<()> {
+        let verdict = simulate(Strategy::Counter, true)?;
+        assert!(verdict.valid, "{verdict}");
+        let verdict = simulate(Strategy::Memory, true)?;
+        assert!(!verdict.valid, "{verdict}");
+        Ok(())
+    }
 }
@@ -1,27 +1,20 @@
-use anyhow::Context;
+use rust_gosssip_gloomers::*;
 use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::hash::Hash;
-use std::io;
-use std::io::{StdoutLock, Write};
+use std::time::Duration;
 use uuid::Uuid;
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Message<P> {
-    src: String,
-    dest: String,
-    body: Body<P>,
-}
+/// How long a batch of deltas is resent to a peer before its deltas are
+/// queued again.
+const GOSSIP_TIMEOUT: Duration = Duration::from_secs(10);
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Body<P> {
-    #[serde(flatten)]
-    payload: P,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    in_reply_to: Option<usize>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    msg_id: Option<usize>,
-}
+/// How deltas are batched into `broad_cast`s, unless overridden by the
+/// environment; see [`BatchConfig::from_env`].
+const BATCHING: BatchConfig = BatchConfig {
+    interval: Duration::from_millis(300),
+    max_size: 100,
+};
 
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -32,134 +25,154 @@ enum Payload {
     Read,
     ReadOk { value: usize },
     BroadCast { log: HashMap<String, usize> },
+    BroadCastOk,
 }
 
-#[derive(Deserialize)]
-struct Init {
-    node_id: String,
-    node_ids: Vec<String>,
+#[derive(Debug, Clone)]
+enum Tick {
+    Flush,
 }
 
-#[derive(Serialize, Debug)]
-#[serde(rename_all = "snake_case")]
-#[serde(tag = "type")]
-struct init_ok {}
-
 #[derive(Debug)]
-struct Node {
-    msg_id: usize,
+struct CounterNode {
     sum: usize,
     log: HashMap<String, usize>,
+    /// Deltas, by key, waiting to be sent to each peer.
+    batcher: Batcher<(String, usize)>,
     node_id: String,
     node_ids: Vec<String>,
 }
 
-impl Node {
-    fn step(&mut self, input: Message<Payload>, output: &mut StdoutLock) -> anyhow::Result<()> {
-        match input.body.payload {
-            Payload::Add { delta } => {
-                self.log.insert(Uuid::new_v4().to_string(), delta);
+impl Node<BatchConfig, Payload, Tick> for CounterNode {
+    fn from_init(
+        batching: BatchConfig,
+        init: Init,
+        scheduler: &mut Scheduler<Payload, Tick>,
+    ) -> anyhow::Result<Self> {
+        scheduler.every(batching.interval, Tick::Flush);
+        Ok(CounterNode {
+            sum: 0,
+            log: HashMap::new(),
+            batcher: Batcher::new(batching),
+            node_id: init.node_id,
+            node_ids: init.node_ids,
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, Tick>,
+        output: &mut Output<Self>,
+    ) -> anyhow::Result<()> {
+        let input = match input {
+            Event::Message(input) => input,
+            Event::Tick(Tick::Flush) => {
+                let batches = self.batcher.flush();
+                return Self::gossip(batches, output);
+            }
+            Event::Eof => return Ok(()),
+        };
+        match &input.body.payload {
+            &Payload::Add { delta } => {
+                let key = Uuid::new_v4().to_string();
+                self.log.insert(key.clone(), delta);
                 self.sum += delta;
-                let response = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: Body {
-                        payload: Payload::AddOk,
-                        msg_id: None,
-                        in_reply_to: input.body.msg_id,
-                    },
-                };
-                serde_json::to_writer(&mut *output, &response)?;
-                output.write_all(b"\n")?;
+                for node in &self.node_ids {
+                    if node != &self.node_id {
+                        self.batcher.push(node, (key.clone(), delta));
+                    }
+                }
+                output.send(&input.reply(Payload::AddOk, output))?;
             }
             Payload::AddOk => {
-                panic!("This code should be unreachable")
+                return Err(Error::not_supported("input type can not be add_ok").into());
             }
             Payload::Read => {
-                let response = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: Body {
-                        payload: Payload::ReadOk { value: self.sum },
-                        in_reply_to: input.body.msg_id,
-                        msg_id: None,
-                    },
-                };
-                serde_json::to_writer(&mut *output, &response)?;
-                output.write_all(b"\n")?;
+                output.send(&input.reply(Payload::ReadOk { value: self.sum }, output))?;
             }
             Payload::ReadOk { .. } => {
-                panic!("This code should be unreachable")
+                return Err(Error::not_supported("input type can not be read_ok").into());
             }
             Payload::BroadCast { log } => {
-                for (key, value) in log {
-                    if !self.log.contains_key(&key) {
-                        self.log.insert(key, value);
+                for (key, &value) in log {
+                    if let Entry::Vacant(entry) = self.log.entry(key.clone()) {
+                        entry.insert(value);
                         self.sum += value;
                     }
                 }
+                output.send(&input.reply(Payload::BroadCastOk, output))?;
             }
+            Payload::BroadCastOk => {}
         }
-        Ok(())
+        let batches = self.batcher.full();
+        Self::gossip(batches, output)
     }
+}
 
-    fn broadcast(&mut self, output: &mut StdoutLock) {
-        for node in &self.node_ids {
-            if node != &self.node_id {
-                let broadcast_message = Message {
-                    src: self.node_id.clone(),
-                    dest: node.to_string(),
-                    body: Body {
-                        payload: Payload::BroadCast {
-                            log: self.log.clone(),
-                        },
-                        in_reply_to: None,
-                        msg_id: Some(self.msg_id),
-                    },
-                };
-                self.msg_id += 1;
-                serde_json::to_writer(&mut *output, &broadcast_message).unwrap();
-                output.write_all(b"\n").unwrap();
-            }
+impl CounterNode {
+    /// Sends each batch of deltas to its peer until it is acknowledged. A
+    /// batch that times out is queued again.
+    fn gossip(
+        batches: Vec<(String, Vec<(String, usize)>)>,
+        output: &mut Output<Self>,
+    ) -> anyhow::Result<()> {
+        for (node, deltas) in batches {
+            output.rpc_with_deadline(
+                node.clone(),
+                Payload::BroadCast {
+                    log: deltas.iter().cloned().collect(),
+                },
+                GOSSIP_TIMEOUT,
+                BATCH_RETRY,
+                move |this: &mut Self, reply: Result<Message<Payload>, Error>, _output| {
+                    if reply.is_err() {
+                        for delta in deltas {
+                            this.batcher.push(&node, delta);
+                        }
+                    }
+                    Ok(())
+                },
+            )?;
         }
+        Ok(())
     }
 }
 
 fn main() -> anyhow::Result<()> {
-    let mut buffer = String::new();
+    run::<_, CounterNode, _, _>(BatchConfig::from_env(BATCHING)?)
+}
 
-    io::stdin()
-        .read_line(&mut buffer)
-        .expect("Failed to read string");
-    let init: Message<Init> = serde_json::from_str(&buffer).expect("Failed to parse INIT message");
-    let mut node = Node {
-        msg_id: 0,
-        sum: 0,
-        log: HashMap::new(),
-        node_id: init.body.payload.node_id,
-        node_ids: init.body.payload.node_ids,
-    };
-    let reply = Message {
-        src: init.dest,
-        dest: init.src,
-        body: Body {
-            in_reply_to: init.body.msg_id,
-            payload: init_ok {},
-            msg_id: None,
-        },
-    };
-    serde_json::to_writer(io::stdout(), &reply).context("Can not serialize")?;
-    io::stdout().write_all(b"\n");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_gosssip_gloomers::checker;
+    use rust_gosssip_gloomers::sim::nemesis::{LatencyDist, PartitionKind};
+    use rust_gosssip_gloomers::sim::{self, workload::WorkloadKind, SimConfig};
 
-    let stdin = std::io::stdin().lock();
-    let inputs = serde_json::Deserializer::from_reader(stdin).into_iter::<Message<Payload>>();
-    let mut stdout = std::io::stdout().lock();
-    for input in inputs {
-        let input = input
-            .context("can not deserialize the input message")
-            .unwrap();
-        node.step(input, &mut stdout).unwrap();
-        node.broadcast(&mut stdout);
+    #[test]
+    fn counts_every_add_despite_partitions() -> anyhow::Result<()> {
+        let mut config = SimConfig {
+            seed: 3,
+            ..SimConfig::new(WorkloadKind::GCounter)
+        };
+        config.nemesis.partitions = vec![PartitionKind::Isolate];
+        let (history, _) = sim::simulate_run::<_, CounterNode, _, _>(BATCHING, &config)?;
+        let verdict = checker::check(config.workload, &history).expect("a g-counter checker");
+        assert!(verdict.valid, "{verdict}");
+        Ok(())
+    }
+
+    #[test]
+    fn gossip_is_not_resent_within_a_round_trip() -> anyhow::Result<()> {
+        let mut config = SimConfig {
+            seed: 4,
+            latency: Duration::from_millis(100),
+            ..SimConfig::new(WorkloadKind::GCounter)
+        };
+        config.nemesis.latency_dist = LatencyDist::Constant;
+        let (history, report) = sim::simulate_run::<_, CounterNode, _, _>(BATCHING, &config)?;
+        let messages_per_op = report.server_messages as f64 / history.pairs().len() as f64;
+        assert!(messages_per_op < 4.0, "{messages_per_op} messages per op");
+        Ok(())
     }
-    Ok(())
 }
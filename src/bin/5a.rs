@@ -1,8 +1,6 @@
-use anyhow::Context;
 use rust_gosssip_gloomers::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::io::{stdin, stdout, Write};
 
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -33,101 +31,79 @@ enum Payload {
     },
 }
 
-impl Node {
-
-    fn add_new_log_msg(&mut self, key: String, msg: usize) -> usize{
-        let offset = self.log.get(&key).or(Some(&vec![])).unwrap().len();
+impl KafkaNode {
+    fn add_new_log_msg(&mut self, key: &str, msg: usize) -> usize {
+        let offset = self.log.get(key).map_or(0, Vec::len);
         self.log
-            .entry(key.clone())
-            .or_insert_with(|| vec![])
+            .entry(key.to_string())
+            .or_default()
             .push(Msg { value: msg, offset });
-        self.committed_offset.entry(key).or_insert(0);
+        self.committed_offset.entry(key.to_string()).or_insert(0);
         offset
     }
 
-    fn commit_offset(&mut self, offsets: HashMap<String, usize>){
-        offsets.into_iter().for_each(|(k, v)| {
-            self.committed_offset.insert(k, v);
+    /// Committed offsets only move forward, so a consumer that lags behind
+    /// can not undo the commits of one that is further ahead.
+    fn commit_offset(&mut self, offsets: &HashMap<String, usize>) {
+        offsets.iter().for_each(|(k, &v)| {
+            let committed = self.committed_offset.entry(k.clone()).or_insert(v);
+            *committed = (*committed).max(v);
         });
     }
 
-    fn step(&mut self, input: Message<Payload>) -> Message<Payload> {
-        match input.body.payload {
+    fn reply(
+        &mut self,
+        input: &Message<Payload>,
+        output: &Output<Self>,
+    ) -> anyhow::Result<Message<Payload>> {
+        let reply = match &input.body.payload {
             Payload::Send { key, msg } => {
-                let offset = self.add_new_log_msg(key, msg);
-                Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: Body {
-                        payload: Payload::SendOk { offset },
-                        in_reply_to: input.body.msg_id,
-                        msg_id: None,
-                    },
-                }
+                let offset = self.add_new_log_msg(key, *msg);
+                input.reply(Payload::SendOk { offset }, output)
             }
             Payload::SendOk { .. } => {
-                unreachable!();
+                return Err(Error::not_supported("input type can not be send_ok").into());
             }
             Payload::Poll { offsets } => {
                 let mut msgs: HashMap<String, Vec<Vec<usize>>> = HashMap::new();
-                for (key, offset) in offsets {
-                    let key_msgs = self.log.get(&key).map_or_else(Vec::new, |log| {
+                for (key, &offset) in offsets {
+                    let key_msgs = self.log.get(key).map_or_else(Vec::new, |log| {
                         log.iter()
                             .skip(offset)
                             .map(|msg| vec![msg.offset, msg.value])
                             .collect()
                     });
-                    msgs.insert(key, key_msgs);
-                }
-                Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: Body {
-                        payload: Payload::PollOk { msgs },
-                        in_reply_to: input.body.msg_id,
-                        msg_id: None,
-                    },
+                    msgs.insert(key.clone(), key_msgs);
                 }
+                input.reply(Payload::PollOk { msgs }, output)
             }
             Payload::PollOk { .. } => {
-                unreachable!();
+                return Err(Error::not_supported("input type can not be poll_ok").into());
             }
             Payload::CommitOffsets { offsets } => {
                 self.commit_offset(offsets);
-                Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: Body {
-                        payload: Payload::CommitOffsetsOk,
-                        msg_id: None,
-                        in_reply_to: input.body.msg_id,
-                    },
-                }
+                input.reply(Payload::CommitOffsetsOk, output)
             }
             Payload::CommitOffsetsOk => {
-                unreachable!();
+                return Err(Error::not_supported("input type can not be commit_offsets_ok").into());
             }
             Payload::ListCommittedOffsets { keys } => {
                 let offsets = self
                     .committed_offset
                     .iter()
-                    .filter(|(key, _)| keys.contains(&key))
+                    .filter(|(key, _)| keys.contains(key))
                     .map(|(key, value)| (key.clone(), *value))
                     .collect();
-                Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: Body {
-                        payload: Payload::ListCommittedOffsetsOk { offsets },
-                        in_reply_to: input.body.msg_id,
-                        msg_id: None,
-                    },
-                }
+                input.reply(Payload::ListCommittedOffsetsOk { offsets }, output)
             }
             Payload::ListCommittedOffsetsOk { .. } => {
-                unreachable!();
+                return Err(Error::not_supported(
+                    "input type can not be list_committed_offsets_ok",
+                )
+                .into());
             }
-        }
+        };
+        Ok(reply)
     }
 }
 
@@ -136,42 +112,53 @@ struct Msg {
     offset: usize,
 }
 
-struct Node {
-    msg_id: usize,
+struct KafkaNode {
     log: HashMap<String, Vec<Msg>>,
     committed_offset: HashMap<String, usize>,
 }
 
-fn main() {
-    let mut buffer = String::new();
+impl Node<(), Payload> for KafkaNode {
+    fn from_init(
+        _state: (),
+        _init: Init,
+        _scheduler: &mut Scheduler<Payload, ()>,
+    ) -> anyhow::Result<Self> {
+        Ok(KafkaNode {
+            log: HashMap::new(),
+            committed_offset: HashMap::new(),
+        })
+    }
+
+    fn step(&mut self, input: Event<Payload>, output: &mut Output<Self>) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let reply = self.reply(&input, output)?;
+        output.send(&reply)
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    run::<_, KafkaNode, _, _>(())
+}
 
-    stdin()
-        .read_line(&mut buffer)
-        .expect("Failed to read string");
-    let init: Message<Init> = serde_json::from_str(&buffer).expect("Failed to parse INIT message");
-    let mut node = Node {
-        msg_id: 0,
-        log: HashMap::new(),
-        committed_offset: HashMap::new(),
-    };
-    let reply = Message {
-        src: init.dest,
-        dest: init.src,
-        body: Body {
-            in_reply_to: init.body.msg_id,
-            payload: init_ok {},
-            msg_id: None,
-        },
-    };
-    serde_json::to_writer(stdout(), &reply)
-        .context("Can not serialize")
-        .unwrap();
-    stdout().write_all(b"\n").unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_gosssip_gloomers::checker;
+    use rust_gosssip_gloomers::sim::{self, workload::WorkloadKind, SimConfig};
 
-    let inputs =
-        serde_json::Deserializer::from_reader(stdin().lock()).into_iter::<Message<Payload>>();
-    for input in inputs {
-        let input = input.unwrap();
-        respond(node.step(input));
+    /// The log lives on a single node, so it is only checked on one.
+    #[test]
+    fn keeps_an_ordered_log_on_one_node() -> anyhow::Result<()> {
+        let config = SimConfig {
+            seed: 5,
+            node_count: 1,
+            ..SimConfig::new(WorkloadKind::Kafka)
+        };
+        let (history, _) = sim::simulate_run::<_, KafkaNode, _, _>((), &config)?;
+        let verdict = checker::check(config.workload, &history).expect("a kafka checker");
+        assert!(verdict.valid, "{verdict}");
+        Ok(())
     }
 }
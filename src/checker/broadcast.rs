@@ -0,0 +1,248 @@
+use super::latency_quantiles;
+use crate::sim::history::History;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+/// What the broadcast checker found. A history is valid when every node
+/// answered a final read, every acknowledged value is in all of them and no
+/// read returned a value nobody broadcast.
+#[derive(Debug, Clone, Serialize)]
+pub struct BroadcastAnalysis {
+    pub valid: bool,
+    pub attempt_count: usize,
+    pub acknowledged_count: usize,
+    /// Nodes that never answered a read, so have no final read.
+    pub missing_final_reads: Vec<String>,
+    /// Acknowledged values missing from final reads, with the nodes that
+    /// miss them.
+    pub lost: BTreeMap<u64, Vec<String>>,
+    /// Values read that were never broadcast.
+    pub unexpected: BTreeSet<u64>,
+    /// Values that some read missed after their broadcast had been
+    /// acknowledged, but that did make it everywhere in the end.
+    pub stale: BTreeSet<u64>,
+    /// How many reads missed a stale value.
+    pub stale_count: usize,
+    /// How long after its broadcast started each value was in every read,
+    /// in milliseconds.
+    pub stable_latencies: BTreeMap<String, f64>,
+}
+
+struct Broadcast {
+    value: u64,
+    invoked: Duration,
+    acknowledged: Option<Duration>,
+}
+
+struct Read {
+    node: String,
+    invoked: Duration,
+    completed: Duration,
+    values: BTreeSet<u64>,
+}
+
+/// Checks a history of `broadcast` and `read` operations. A node's final
+/// read is the last read it answered.
+pub fn check(history: &History) -> BroadcastAnalysis {
+    let mut broadcasts = Vec::new();
+    let mut reads = Vec::new();
+    let mut nodes = BTreeSet::new();
+    for pair in history.pairs() {
+        nodes.insert(pair.invoke.node.clone());
+        match pair.invoke.f.as_str() {
+            "broadcast" => {
+                if let Some(value) = pair.invoke.value.as_u64() {
+                    broadcasts.push(Broadcast {
+                        value,
+                        invoked: pair.invoke.time(),
+                        acknowledged: pair.completion.filter(|_| pair.is_ok()).map(|op| op.time()),
+                    });
+                }
+            }
+            "read" if pair.is_ok() => {
+                let completion = pair.completion.expect("ok reads have a completion");
+                reads.push(Read {
+                    node: pair.invoke.node.clone(),
+                    invoked: pair.invoke.time(),
+                    completed: completion.time(),
+                    values: completion
+                        .value
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|value| value.as_u64())
+                        .collect(),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    let mut final_reads: BTreeMap<&str, &Read> = BTreeMap::new();
+    for read in &reads {
+        let latest = final_reads.entry(&read.node).or_insert(read);
+        if read.invoked >= latest.invoked {
+            *latest = read;
+        }
+    }
+    let missing_final_reads: Vec<String> = nodes
+        .iter()
+        .filter(|node| !final_reads.contains_key(node.as_str()))
+        .cloned()
+        .collect();
+
+    let attempted: BTreeSet<u64> = broadcasts.iter().map(|b| b.value).collect();
+    let acknowledged: Vec<&Broadcast> = broadcasts
+        .iter()
+        .filter(|b| b.acknowledged.is_some())
+        .collect();
+
+    let mut lost = BTreeMap::new();
+    for broadcast in &acknowledged {
+        let missing: Vec<String> = final_reads
+            .values()
+            .filter(|read| !read.values.contains(&broadcast.value))
+            .map(|read| read.node.clone())
+            .collect();
+        if !missing.is_empty() {
+            lost.insert(broadcast.value, missing);
+        }
+    }
+
+    let unexpected: BTreeSet<u64> = reads
+        .iter()
+        .flat_map(|read| &read.values)
+        .filter(|value| !attempted.contains(value))
+        .copied()
+        .collect();
+
+    let mut stale = BTreeSet::new();
+    let mut stale_count = 0;
+    for read in &reads {
+        let mut is_stale = false;
+        for broadcast in &acknowledged {
+            let before = broadcast
+                .acknowledged
+                .is_some_and(|acknowledged| acknowledged < read.invoked);
+            if before
+                && !read.values.contains(&broadcast.value)
+                && !lost.contains_key(&broadcast.value)
+            {
+                stale.insert(broadcast.value);
+                is_stale = true;
+            }
+        }
+        if is_stale {
+            stale_count += 1;
+        }
+    }
+
+    // A value is stable once no later read misses it.
+    let stable_latencies = acknowledged
+        .iter()
+        .filter(|broadcast| !lost.contains_key(&broadcast.value))
+        .map(|broadcast| {
+            let stable = reads
+                .iter()
+                .filter(|read| {
+                    read.invoked >= broadcast.invoked && !read.values.contains(&broadcast.value)
+                })
+                .map(|read| read.completed)
+                .max()
+                .unwrap_or(broadcast.invoked);
+            stable.saturating_sub(broadcast.invoked)
+        })
+        .collect();
+
+    BroadcastAnalysis {
+        valid: lost.is_empty() && unexpected.is_empty() && missing_final_reads.is_empty(),
+        attempt_count: broadcasts.len(),
+        acknowledged_count: acknowledged.len(),
+        missing_final_reads,
+        lost,
+        unexpected,
+        stale,
+        stale_count,
+        stable_latencies: latency_quantiles(stable_latencies),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker::history;
+    use serde_json::json;
+
+    #[test]
+    fn values_read_everywhere_in_the_end_are_valid() {
+        let analysis = check(&history(&[
+            (0, "invoke", "broadcast", json!(1), 0),
+            (0, "ok", "broadcast", json!(1), 10),
+            (1, "invoke", "read", json!(null), 20),
+            (1, "ok", "read", json!([]), 30),
+            (0, "invoke", "read", json!(null), 40),
+            (0, "ok", "read", json!([1]), 50),
+            (1, "invoke", "read", json!(null), 60),
+            (1, "ok", "read", json!([1]), 70),
+        ]));
+        assert!(analysis.valid);
+        assert_eq!(analysis.stale, BTreeSet::from([1]));
+        assert_eq!(analysis.stale_count, 1);
+        assert!((analysis.stable_latencies["1"] - 30e-6).abs() < 1e-12);
+    }
+
+    #[test]
+    fn acknowledged_values_missing_from_a_final_read_are_lost() {
+        let analysis = check(&history(&[
+            (0, "invoke", "broadcast", json!(1), 0),
+            (0, "ok", "broadcast", json!(1), 10),
+            (0, "invoke", "read", json!(null), 20),
+            (0, "ok", "read", json!([1]), 30),
+            (1, "invoke", "read", json!(null), 20),
+            (1, "ok", "read", json!([]), 30),
+        ]));
+        assert!(!analysis.valid);
+        assert_eq!(analysis.lost, BTreeMap::from([(1, vec!["n1".to_string()])]));
+        assert!(analysis.stale.is_empty());
+    }
+
+    #[test]
+    fn unacknowledged_values_may_be_missing() {
+        let analysis = check(&history(&[
+            (0, "invoke", "broadcast", json!(1), 0),
+            (0, "info", "broadcast", json!(1), 10),
+            (0, "invoke", "read", json!(null), 20),
+            (0, "ok", "read", json!([1]), 30),
+            (1, "invoke", "read", json!(null), 20),
+            (1, "ok", "read", json!([]), 30),
+        ]));
+        assert!(analysis.valid);
+        assert_eq!(
+            (analysis.attempt_count, analysis.acknowledged_count),
+            (1, 0)
+        );
+    }
+
+    #[test]
+    fn values_nobody_broadcast_are_unexpected() {
+        let analysis = check(&history(&[
+            (0, "invoke", "read", json!(null), 0),
+            (0, "ok", "read", json!([7]), 10),
+        ]));
+        assert!(!analysis.valid);
+        assert_eq!(analysis.unexpected, BTreeSet::from([7]));
+    }
+
+    #[test]
+    fn nodes_without_a_read_miss_their_final_read() {
+        let analysis = check(&history(&[
+            (0, "invoke", "broadcast", json!(1), 0),
+            (0, "ok", "broadcast", json!(1), 10),
+            (1, "invoke", "read", json!(null), 20),
+            (1, "ok", "read", json!([1]), 30),
+        ]));
+        assert!(!analysis.valid);
+        assert_eq!(analysis.missing_final_reads, vec!["n0".to_string()]);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::sim::history::{History, Pair, Process};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+/// How many stale reads the analysis lists; under message loss most reads
+/// can be stale, and the first few show the pattern.
+const STALE_READS_SHOWN: usize = 16;
+
+/// What the g-counter checker found.
+///
+/// Every read has to lie within bounds. It can not be lower than the sum of
+/// the adds acknowledged before it began, nor higher than the sum of the adds
+/// that may have happened and began before it ended. The counter is only
+/// eventually consistent, so an intermediate read below its lower bound is
+/// merely stale: it is counted in `stale_count`, but deliberately does not
+/// make the history invalid. A read above its upper bound, or a final read
+/// outside of `[acknowledged_sum, attempted_sum]`, does.
+#[derive(Debug, Clone, Serialize)]
+pub struct GCounterAnalysis {
+    pub valid: bool,
+    /// The sum of the acknowledged adds.
+    pub acknowledged_sum: u64,
+    /// The sum of the adds that may have happened.
+    pub attempted_sum: u64,
+    /// Nodes that never answered a read, so have no final read.
+    pub missing_final_reads: Vec<String>,
+    /// Final reads outside of `[acknowledged_sum, attempted_sum]`.
+    pub invalid_final_reads: Vec<OffendingRead>,
+    /// Reads higher than any sum the adds could have produced by then.
+    pub impossible_reads: Vec<OffendingRead>,
+    /// How many intermediate reads missed adds acknowledged before they
+    /// began. These do not count against `valid`.
+    pub stale_count: usize,
+    /// The first of those reads.
+    pub stale_reads: Vec<OffendingRead>,
+}
+
+/// A read that fell outside of its bounds.
+#[derive(Debug, Clone, Serialize)]
+pub struct OffendingRead {
+    pub process: Process,
+    pub node: String,
+    /// When the read began, in nanoseconds since the start of the run.
+    pub time: u64,
+    pub value: u64,
+    pub lower: u64,
+    pub upper: u64,
+}
+
+struct Add {
+    delta: u64,
+    invoked: Duration,
+    acknowledged: Option<Duration>,
+}
+
+/// Checks a history of `add` and `read` operations. A node's final read is
+/// the last read it answered.
+pub fn check(history: &History) -> GCounterAnalysis {
+    let mut adds = Vec::new();
+    let mut reads: Vec<(Pair, u64)> = Vec::new();
+    let mut nodes = BTreeSet::new();
+    for pair in history.pairs() {
+        nodes.insert(pair.invoke.node.clone());
+        match pair.invoke.f.as_str() {
+            "add" if pair.may_have_happened() => {
+                adds.push(Add {
+                    delta: pair.invoke.value.as_u64().unwrap_or_default(),
+                    invoked: pair.invoke.time(),
+                    acknowledged: pair.completion.filter(|_| pair.is_ok()).map(|op| op.time()),
+                });
+            }
+            "read" if pair.is_ok() => {
+                let completion = pair.completion.expect("ok reads have a completion");
+                reads.push((pair, completion.value.as_u64().unwrap_or_default()));
+            }
+            _ => {}
+        }
+    }
+
+    let acknowledged_sum = adds
+        .iter()
+        .filter(|add| add.acknowledged.is_some())
+        .map(|add| add.delta)
+        .sum();
+    let attempted_sum = adds.iter().map(|add| add.delta).sum();
+
+    let mut final_reads: BTreeMap<&str, usize> = BTreeMap::new();
+    for (i, (pair, _)) in reads.iter().enumerate() {
+        let latest = final_reads.entry(&pair.invoke.node).or_insert(i);
+        if pair.invoke.time >= reads[*latest].0.invoke.time {
+            *latest = i;
+        }
+    }
+    let missing_final_reads: Vec<String> = nodes
+        .iter()
+        .filter(|node| !final_reads.contains_key(node.as_str()))
+        .cloned()
+        .collect();
+
+    let mut invalid_final_reads = Vec::new();
+    let mut impossible_reads = Vec::new();
+    let mut stale_reads = Vec::new();
+    let mut stale_count = 0;
+    for (i, (pair, value)) in reads.iter().enumerate() {
+        let invoked = pair.invoke.time();
+        let completed = pair.completion.expect("ok reads have a completion").time();
+        let offending = |lower, upper| OffendingRead {
+            process: pair.invoke.process,
+            node: pair.invoke.node.clone(),
+            time: pair.invoke.time,
+            value: *value,
+            lower,
+            upper,
+        };
+
+        if final_reads.get(pair.invoke.node.as_str()) == Some(&i)
+            && !(acknowledged_sum..=attempted_sum).contains(value)
+        {
+            invalid_final_reads.push(offending(acknowledged_sum, attempted_sum));
+            continue;
+        }
+
+        let lower = adds
+            .iter()
+            .filter(|add| add.acknowledged.is_some_and(|at| at < invoked))
+            .map(|add| add.delta)
+            .sum();
+        let upper = adds
+            .iter()
+            .filter(|add| add.invoked <= completed)
+            .map(|add| add.delta)
+            .sum();
+        if *value > upper {
+            impossible_reads.push(offending(lower, upper));
+        } else if *value < lower {
+            stale_count += 1;
+            if stale_reads.len() < STALE_READS_SHOWN {
+                stale_reads.push(offending(lower, upper));
+            }
+        }
+    }
+
+    GCounterAnalysis {
+        valid: missing_final_reads.is_empty()
+            && invalid_final_reads.is_empty()
+            && impossible_reads.is_empty(),
+        acknowledged_sum,
+        attempted_sum,
+        missing_final_reads,
+        invalid_final_reads,
+        impossible_reads,
+        stale_count,
+        stale_reads,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker::history;
+    use serde_json::json;
+
+    #[test]
+    fn stale_reads_are_reported_but_valid() {
+        let analysis = check(&history(&[
+            (0, "invoke", "add", json!(2), 0),
+            (0, "ok", "add", json!(2), 10),
+            (1, "invoke", "read", json!(null), 20),
+            (1, "ok", "read", json!(0), 30),
+            (1, "invoke", "read", json!(null), 40),
+            (1, "ok", "read", json!(2), 50),
+            (0, "invoke", "read", json!(null), 60),
+            (0, "ok", "read", json!(2), 70),
+        ]));
+        assert!(analysis.valid);
+        assert_eq!(analysis.stale_count, 1);
+        assert_eq!(analysis.stale_reads.len(), 1);
+        let stale = &analysis.stale_reads[0];
+        assert_eq!((stale.node.as_str(), stale.time), ("n1", 20));
+        assert_eq!((stale.value, stale.lower, stale.upper), (0, 2, 2));
+    }
+
+    #[test]
+    fn only_the_first_stale_reads_are_listed() {
+        let mut ops = vec![
+            (0, "invoke", "add", json!(1), 0),
+            (0, "ok", "add", json!(1), 10),
+        ];
+        for i in 0..40 {
+            ops.push((1, "invoke", "read", json!(null), 20 + 20 * i));
+            ops.push((1, "ok", "read", json!(0), 30 + 20 * i));
+        }
+        ops.push((1, "invoke", "read", json!(null), 1000));
+        ops.push((1, "ok", "read", json!(1), 1010));
+        ops.push((0, "invoke", "read", json!(null), 1000));
+        ops.push((0, "ok", "read", json!(1), 1010));
+        let analysis = check(&history(&ops));
+        assert!(analysis.valid);
+        assert_eq!(analysis.stale_count, 40);
+        assert_eq!(analysis.stale_reads.len(), STALE_READS_SHOWN);
+        assert_eq!(analysis.stale_reads[0].time, 20);
+    }
+
+    #[test]
+    fn reads_above_their_upper_bound_are_impossible() {
+        let analysis = check(&history(&[
+            (0, "invoke", "read", json!(null), 0),
+            (0, "ok", "read", json!(5), 10),
+            (1, "invoke", "add", json!(5), 20),
+            (1, "ok", "add", json!(5), 30),
+            (0, "invoke", "read", json!(null), 40),
+            (0, "ok", "read", json!(5), 50),
+            (1, "invoke", "read", json!(null), 60),
+            (1, "ok", "read", json!(5), 70),
+        ]));
+        assert!(!analysis.valid);
+        assert_eq!(analysis.impossible_reads.len(), 1);
+        assert_eq!(analysis.impossible_reads[0].upper, 0);
+    }
+
+    #[test]
+    fn final_reads_must_see_every_acknowledged_add() {
+        let analysis = check(&history(&[
+            (0, "invoke", "add", json!(1), 0),
+            (0, "ok", "add", json!(1), 10),
+            (1, "invoke", "add", json!(4), 20),
+            (1, "info", "add", json!(4), 30),
+            (0, "invoke", "read", json!(null), 40),
+            (0, "ok", "read", json!(0), 50),
+        ]));
+        assert!(!analysis.valid);
+        assert_eq!((analysis.acknowledged_sum, analysis.attempted_sum), (1, 5));
+        assert_eq!(analysis.invalid_final_reads.len(), 1);
+        assert_eq!(analysis.missing_final_reads, vec!["n1".to_string()]);
+    }
+}
@@ -0,0 +1,465 @@
+use crate::sim::history::{History, Process};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::Duration;
+
+/// What the kafka checker found. A history is valid when it shows none of
+/// the anomalies below.
+#[derive(Debug, Clone, Serialize)]
+pub struct KafkaAnalysis {
+    pub valid: bool,
+    pub send_count: usize,
+    pub acknowledged_count: usize,
+    pub poll_count: usize,
+    /// Offsets of a key that hold more than one message.
+    pub duplicate_offsets: Vec<DuplicateOffset>,
+    /// Acknowledged sends that no poll ever returned, although polls got
+    /// past their offset.
+    pub lost_writes: Vec<LogEntry>,
+    /// Sends that got an offset no higher than that of a send acknowledged
+    /// before they began.
+    pub nonmonotonic_sends: Vec<NonmonotonicSend>,
+    /// Polls whose offsets did not go up, either within the poll or since
+    /// the last poll of the same process.
+    pub nonmonotonic_polls: Vec<NonmonotonicPoll>,
+    /// Polls that left out an offset acknowledged before they began.
+    pub skipped_offsets: Vec<SkippedOffset>,
+    /// Committed offsets that went down.
+    pub backward_commits: Vec<BackwardCommit>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateOffset {
+    pub key: String,
+    pub offset: u64,
+    pub msgs: BTreeSet<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    pub key: String,
+    pub offset: u64,
+    pub msg: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NonmonotonicSend {
+    pub key: String,
+    pub earlier: LogEntry,
+    pub later: LogEntry,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NonmonotonicPoll {
+    pub process: Process,
+    pub key: String,
+    /// When the poll began, in nanoseconds since the start of the run.
+    pub time: u64,
+    /// The highest offset the process had polled before.
+    pub previous: Option<u64>,
+    pub offsets: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedOffset {
+    pub process: Process,
+    /// When the poll began, in nanoseconds since the start of the run.
+    pub time: u64,
+    pub skipped: LogEntry,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackwardCommit {
+    pub key: String,
+    /// When the committed offset was seen to be lower, in nanoseconds since
+    /// the start of the run.
+    pub time: u64,
+    pub from: u64,
+    pub to: u64,
+}
+
+struct Send {
+    key: String,
+    msg: u64,
+    invoked: Duration,
+    /// The offset and the time of the acknowledgement.
+    acknowledged: Option<(u64, Duration)>,
+}
+
+struct Poll {
+    process: Process,
+    invoked: Duration,
+    time: u64,
+    requested: BTreeMap<String, u64>,
+    /// Every key polled, with the `(offset, msg)` pairs returned.
+    msgs: BTreeMap<String, Vec<(u64, u64)>>,
+}
+
+/// Committed offsets, either acknowledged by `commit_offsets` or returned by
+/// `list_committed_offsets`.
+struct Offsets {
+    /// Whether they were listed, as opposed to committed.
+    listed: bool,
+    invoked: Duration,
+    completed: Duration,
+    time: u64,
+    offsets: BTreeMap<String, u64>,
+}
+
+/// Checks a history of `send`, `poll`, `commit_offsets` and
+/// `list_committed_offsets` operations.
+pub fn check(history: &History) -> KafkaAnalysis {
+    let mut sends = Vec::new();
+    let mut polls = Vec::new();
+    let mut offsets = Vec::new();
+    for pair in history.pairs() {
+        let completion = pair.completion.filter(|_| pair.is_ok());
+        match pair.invoke.f.as_str() {
+            "send" => {
+                let (Some(key), Some(msg)) =
+                    (pair.invoke.value[0].as_str(), pair.invoke.value[1].as_u64())
+                else {
+                    continue;
+                };
+                sends.push(Send {
+                    key: key.to_string(),
+                    msg,
+                    invoked: pair.invoke.time(),
+                    acknowledged: completion
+                        .and_then(|op| Some((op.value[2].as_u64()?, op.time()))),
+                });
+            }
+            "poll" => {
+                let Some(completion) = completion else {
+                    continue;
+                };
+                polls.push(Poll {
+                    process: pair.invoke.process,
+                    invoked: pair.invoke.time(),
+                    time: pair.invoke.time,
+                    requested: offset_map(&pair.invoke.value),
+                    msgs: completion
+                        .value
+                        .as_object()
+                        .into_iter()
+                        .flatten()
+                        .map(|(key, msgs)| {
+                            let msgs = msgs
+                                .as_array()
+                                .into_iter()
+                                .flatten()
+                                .filter_map(|msg| Some((msg[0].as_u64()?, msg[1].as_u64()?)))
+                                .collect();
+                            (key.clone(), msgs)
+                        })
+                        .collect(),
+                });
+            }
+            f @ ("commit_offsets" | "list_committed_offsets") => {
+                let Some(completion) = completion else {
+                    continue;
+                };
+                offsets.push(Offsets {
+                    listed: f == "list_committed_offsets",
+                    invoked: pair.invoke.time(),
+                    completed: completion.time(),
+                    time: pair.invoke.time,
+                    offsets: offset_map(&completion.value),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    let acknowledged: Vec<(&Send, u64, Duration)> = sends
+        .iter()
+        .filter_map(|send| {
+            send.acknowledged
+                .map(|(offset, acknowledged)| (send, offset, acknowledged))
+        })
+        .collect();
+
+    // Everything known to sit at an offset, from acknowledgements and polls.
+    let mut log: BTreeMap<(&str, u64), BTreeSet<u64>> = BTreeMap::new();
+    for &(send, offset, _) in &acknowledged {
+        log.entry((&send.key, offset)).or_default().insert(send.msg);
+    }
+    let mut polled: BTreeSet<(&str, u64)> = BTreeSet::new();
+    let mut highest_polled: HashMap<&str, u64> = HashMap::new();
+    for poll in &polls {
+        for (key, msgs) in &poll.msgs {
+            for &(offset, msg) in msgs {
+                log.entry((key, offset)).or_default().insert(msg);
+                polled.insert((key, msg));
+                let highest = highest_polled.entry(key).or_insert(offset);
+                *highest = (*highest).max(offset);
+            }
+        }
+    }
+    let duplicate_offsets: Vec<DuplicateOffset> = log
+        .iter()
+        .filter(|(_, msgs)| msgs.len() > 1)
+        .map(|(&(key, offset), msgs)| DuplicateOffset {
+            key: key.to_string(),
+            offset,
+            msgs: msgs.clone(),
+        })
+        .collect();
+
+    let lost_writes: Vec<LogEntry> = acknowledged
+        .iter()
+        .filter(|&&(send, offset, _)| {
+            highest_polled
+                .get(send.key.as_str())
+                .is_some_and(|&highest| highest > offset)
+                && !polled.contains(&(send.key.as_str(), send.msg))
+        })
+        .map(|&(send, offset, _)| LogEntry {
+            key: send.key.clone(),
+            offset,
+            msg: send.msg,
+        })
+        .collect();
+
+    let mut nonmonotonic_sends = Vec::new();
+    for &(earlier, earlier_offset, acknowledged_at) in &acknowledged {
+        for &(later, later_offset, _) in &acknowledged {
+            if later.key == earlier.key
+                && acknowledged_at < later.invoked
+                && later_offset <= earlier_offset
+            {
+                nonmonotonic_sends.push(NonmonotonicSend {
+                    key: earlier.key.clone(),
+                    earlier: LogEntry {
+                        key: earlier.key.clone(),
+                        offset: earlier_offset,
+                        msg: earlier.msg,
+                    },
+                    later: LogEntry {
+                        key: later.key.clone(),
+                        offset: later_offset,
+                        msg: later.msg,
+                    },
+                });
+            }
+        }
+    }
+
+    let mut nonmonotonic_polls = Vec::new();
+    let mut skipped_offsets = Vec::new();
+    let mut positions: HashMap<(Process, &str), u64> = HashMap::new();
+    for poll in &polls {
+        for (key, msgs) in &poll.msgs {
+            let offsets: Vec<u64> = msgs.iter().map(|&(offset, _)| offset).collect();
+            let previous = positions.get(&(poll.process, key.as_str())).copied();
+            let increasing = offsets.windows(2).all(|pair| pair[0] < pair[1]);
+            let advanced = previous
+                .is_none_or(|previous| offsets.first().is_none_or(|&first| first > previous));
+            if !increasing || !advanced {
+                nonmonotonic_polls.push(NonmonotonicPoll {
+                    process: poll.process,
+                    key: key.clone(),
+                    time: poll.time,
+                    previous,
+                    offsets: offsets.clone(),
+                });
+            }
+            if let Some(&last) = offsets.iter().max() {
+                let position = positions.entry((poll.process, key)).or_insert(last);
+                *position = (*position).max(last);
+            }
+
+            let (Some(&from), Some(&to)) = (poll.requested.get(key), offsets.iter().max()) else {
+                continue;
+            };
+            for &(send, offset, acknowledged_at) in &acknowledged {
+                if send.key == *key
+                    && acknowledged_at < poll.invoked
+                    && (from..to).contains(&offset)
+                    && !offsets.contains(&offset)
+                {
+                    skipped_offsets.push(SkippedOffset {
+                        process: poll.process,
+                        time: poll.time,
+                        skipped: LogEntry {
+                            key: key.clone(),
+                            offset,
+                            msg: send.msg,
+                        },
+                    });
+                }
+            }
+        }
+    }
+
+    // Once a commit is acknowledged or an offset listed, no list that
+    // begins later may return a lower offset.
+    let mut backward_commits = Vec::new();
+    for list in offsets.iter().filter(|offsets| offsets.listed) {
+        for (key, &to) in &list.offsets {
+            let from = offsets
+                .iter()
+                .filter(|earlier| earlier.completed < list.invoked)
+                .filter_map(|earlier| earlier.offsets.get(key))
+                .max();
+            if let Some(&from) = from.filter(|&&from| from > to) {
+                backward_commits.push(BackwardCommit {
+                    key: key.clone(),
+                    time: list.time,
+                    from,
+                    to,
+                });
+            }
+        }
+    }
+
+    KafkaAnalysis {
+        valid: duplicate_offsets.is_empty()
+            && lost_writes.is_empty()
+            && nonmonotonic_sends.is_empty()
+            && nonmonotonic_polls.is_empty()
+            && skipped_offsets.is_empty()
+            && backward_commits.is_empty(),
+        send_count: sends.len(),
+        acknowledged_count: acknowledged.len(),
+        poll_count: polls.len(),
+        duplicate_offsets,
+        lost_writes,
+        nonmonotonic_sends,
+        nonmonotonic_polls,
+        skipped_offsets,
+        backward_commits,
+    }
+}
+
+/// Reads a `{key: offset}` object.
+fn offset_map(value: &Value) -> BTreeMap<String, u64> {
+    value
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(key, offset)| Some((key.clone(), offset.as_u64()?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker::history;
+    use serde_json::json;
+
+    #[test]
+    fn a_log_read_in_order_is_valid() {
+        let analysis = check(&history(&[
+            (0, "invoke", "send", json!(["k", 1]), 0),
+            (0, "ok", "send", json!(["k", 1, 0]), 10),
+            (0, "invoke", "send", json!(["k", 2]), 20),
+            (0, "ok", "send", json!(["k", 2, 1]), 30),
+            (1, "invoke", "poll", json!({"k": 0}), 40),
+            (1, "ok", "poll", json!({"k": [[0, 1], [1, 2]]}), 50),
+            (1, "invoke", "commit_offsets", json!({"k": 1}), 60),
+            (1, "ok", "commit_offsets", json!({"k": 1}), 70),
+            (1, "invoke", "list_committed_offsets", json!(["k"]), 80),
+            (1, "ok", "list_committed_offsets", json!({"k": 1}), 90),
+        ]));
+        assert!(analysis.valid, "{analysis:?}");
+        assert_eq!((analysis.send_count, analysis.acknowledged_count), (2, 2));
+        assert_eq!(analysis.poll_count, 1);
+    }
+
+    #[test]
+    fn two_messages_at_one_offset_are_duplicates() {
+        let analysis = check(&history(&[
+            (0, "invoke", "send", json!(["k", 1]), 0),
+            (0, "ok", "send", json!(["k", 1, 0]), 10),
+            (1, "invoke", "send", json!(["k", 2]), 0),
+            (1, "ok", "send", json!(["k", 2, 0]), 10),
+        ]));
+        assert!(!analysis.valid);
+        assert_eq!(analysis.duplicate_offsets.len(), 1);
+        assert_eq!(analysis.duplicate_offsets[0].msgs, BTreeSet::from([1, 2]));
+    }
+
+    #[test]
+    fn acknowledged_sends_polled_past_are_lost() {
+        let analysis = check(&history(&[
+            (0, "invoke", "send", json!(["k", 1]), 0),
+            (0, "ok", "send", json!(["k", 1, 0]), 10),
+            (0, "invoke", "send", json!(["k", 2]), 20),
+            (0, "ok", "send", json!(["k", 2, 1]), 30),
+            (1, "invoke", "poll", json!({"k": 1}), 40),
+            (1, "ok", "poll", json!({"k": [[1, 2]]}), 50),
+        ]));
+        assert!(!analysis.valid);
+        assert_eq!(analysis.lost_writes.len(), 1);
+        assert_eq!(analysis.lost_writes[0].msg, 1);
+        assert!(analysis.skipped_offsets.is_empty());
+    }
+
+    #[test]
+    fn polls_that_leave_out_an_acknowledged_offset_skip_it() {
+        let analysis = check(&history(&[
+            (0, "invoke", "send", json!(["k", 1]), 0),
+            (0, "ok", "send", json!(["k", 1, 0]), 10),
+            (0, "invoke", "send", json!(["k", 2]), 20),
+            (0, "ok", "send", json!(["k", 2, 1]), 30),
+            (1, "invoke", "poll", json!({"k": 0}), 40),
+            (1, "ok", "poll", json!({"k": [[1, 2]]}), 50),
+        ]));
+        assert!(!analysis.valid);
+        assert_eq!(analysis.skipped_offsets.len(), 1);
+        assert_eq!(analysis.skipped_offsets[0].skipped.offset, 0);
+    }
+
+    #[test]
+    fn later_sends_must_get_higher_offsets() {
+        let analysis = check(&history(&[
+            (0, "invoke", "send", json!(["k", 1]), 0),
+            (0, "ok", "send", json!(["k", 1, 5]), 10),
+            (1, "invoke", "send", json!(["k", 2]), 20),
+            (1, "ok", "send", json!(["k", 2, 3]), 30),
+        ]));
+        assert!(!analysis.valid);
+        assert_eq!(analysis.nonmonotonic_sends.len(), 1);
+        let send = &analysis.nonmonotonic_sends[0];
+        assert_eq!((send.earlier.offset, send.later.offset), (5, 3));
+    }
+
+    #[test]
+    fn polls_must_move_forward() {
+        let analysis = check(&history(&[
+            (0, "invoke", "send", json!(["k", 1]), 0),
+            (0, "ok", "send", json!(["k", 1, 0]), 10),
+            (0, "invoke", "send", json!(["k", 2]), 20),
+            (0, "ok", "send", json!(["k", 2, 1]), 30),
+            (1, "invoke", "poll", json!({"k": 0}), 40),
+            (1, "ok", "poll", json!({"k": [[0, 1], [1, 2]]}), 50),
+            (1, "invoke", "poll", json!({"k": 1}), 60),
+            (1, "ok", "poll", json!({"k": [[1, 2]]}), 70),
+            (2, "invoke", "poll", json!({"k": 0}), 80),
+            (2, "ok", "poll", json!({"k": [[1, 2], [0, 1]]}), 90),
+        ]));
+        assert!(!analysis.valid);
+        let polls: Vec<(u64, Option<u64>)> = analysis
+            .nonmonotonic_polls
+            .iter()
+            .map(|poll| (poll.time, poll.previous))
+            .collect();
+        assert_eq!(polls, vec![(60, Some(1)), (80, None)]);
+    }
+
+    #[test]
+    fn committed_offsets_must_not_go_down() {
+        let analysis = check(&history(&[
+            (0, "invoke", "commit_offsets", json!({"k": 5}), 0),
+            (0, "ok", "commit_offsets", json!({"k": 5}), 10),
+            (1, "invoke", "list_committed_offsets", json!(["k"]), 20),
+            (1, "ok", "list_committed_offsets", json!({"k": 3}), 30),
+        ]));
+        assert!(!analysis.valid);
+        assert_eq!(analysis.backward_commits.len(), 1);
+        let commit = &analysis.backward_commits[0];
+        assert_eq!((commit.from, commit.to, commit.time), (5, 3, 20));
+    }
+}
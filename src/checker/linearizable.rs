@@ -0,0 +1,373 @@
+use crate::sim::history::{History, Op, OpType, Process};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::time::Duration;
+
+/// What the linearizability checker found. Every key is an independent
+/// register, checked on its own.
+#[derive(Debug, Clone, Serialize)]
+pub struct LinearizableAnalysis {
+    pub valid: bool,
+    pub key_count: usize,
+    pub op_count: usize,
+    /// The first key, in key order, that is not linearizable.
+    pub counterexample: Option<Counterexample>,
+}
+
+/// The smallest prefix of a key's history that can not be linearized,
+/// reduced to the operation that breaks it and the ones it overlaps with.
+#[derive(Debug, Clone, Serialize)]
+pub struct Counterexample {
+    pub key: Value,
+    /// The operation whose completion made the history non-linearizable.
+    pub op: Call,
+    /// Operations that overlap `op` in real time.
+    pub concurrent: Vec<Call>,
+    /// Values the register may hold once every operation that completed
+    /// before `op` has taken effect; `null` stands for a missing key.
+    pub possible_values: Vec<Value>,
+}
+
+/// An operation on a single register.
+#[derive(Debug, Clone, Serialize)]
+pub struct Call {
+    pub process: Process,
+    pub f: String,
+    pub value: Value,
+    /// When the operation began and completed, in nanoseconds since the
+    /// start of the run. Operations with an unknown outcome never complete.
+    pub invoke: u64,
+    pub complete: Option<u64>,
+    #[serde(skip)]
+    op: RegisterOp,
+}
+
+#[derive(Debug, Clone)]
+enum RegisterOp {
+    Read(Option<Value>),
+    Write(Value),
+    Cas(Value, Value),
+}
+
+impl Call {
+    fn invoked(&self) -> Duration {
+        Duration::from_nanos(self.invoke)
+    }
+
+    fn completed(&self) -> Option<Duration> {
+        self.complete.map(Duration::from_nanos)
+    }
+
+    /// The state after applying the operation to `state`, if it is legal.
+    fn apply(&self, state: &Option<Value>) -> Option<Option<Value>> {
+        match &self.op {
+            RegisterOp::Read(value) => (value == state).then(|| state.clone()),
+            RegisterOp::Write(value) => Some(Some(value.clone())),
+            RegisterOp::Cas(from, to) => (state.as_ref() == Some(from)).then(|| Some(to.clone())),
+        }
+    }
+}
+
+/// Checks a history of `read`, `write` and `cas` operations on independent
+/// keys, as Maelstrom's `lin-kv` workload records them: the value of a
+/// read is `[key, value]`, of a write `[key, value]` and of a cas
+/// `[key, [from, to]]`. Missing keys start out absent.
+pub fn check(history: &History) -> LinearizableAnalysis {
+    let mut keys: BTreeMap<String, (Value, Vec<Call>)> = BTreeMap::new();
+    let mut op_count = 0;
+    for pair in history.pairs() {
+        let Some((key, call)) = call(pair.invoke, pair.completion) else {
+            continue;
+        };
+        op_count += 1;
+        keys.entry(key.to_string())
+            .or_insert_with(|| (key, Vec::new()))
+            .1
+            .push(call);
+    }
+
+    let counterexample = keys
+        .values()
+        .find_map(|(key, calls)| counterexample(key, calls));
+    LinearizableAnalysis {
+        valid: counterexample.is_none(),
+        key_count: keys.len(),
+        op_count,
+        counterexample,
+    }
+}
+
+/// Turns a completed pair into a register operation. Failed operations did
+/// not happen and reads with an unknown outcome constrain nothing, so both
+/// are left out.
+fn call(invoke: &Op, completion: Option<&Op>) -> Option<(Value, Call)> {
+    let kind = completion.map_or(OpType::Info, |op| op.kind);
+    if kind == OpType::Fail || (kind == OpType::Info && invoke.f == "read") {
+        return None;
+    }
+    let value = if kind == OpType::Ok {
+        &completion?.value
+    } else {
+        &invoke.value
+    };
+    let key = value.get(0)?.clone();
+    let argument = value.get(1).cloned().unwrap_or(Value::Null);
+    let op = match invoke.f.as_str() {
+        "read" => RegisterOp::Read(Some(argument.clone()).filter(|value| !value.is_null())),
+        "write" => RegisterOp::Write(argument.clone()),
+        "cas" => RegisterOp::Cas(argument.get(0)?.clone(), argument.get(1)?.clone()),
+        _ => return None,
+    };
+    Some((
+        key,
+        Call {
+            process: invoke.process,
+            f: invoke.f.clone(),
+            value: value.clone(),
+            invoke: invoke.time,
+            complete: completion
+                .filter(|op| op.kind == OpType::Ok)
+                .map(|op| op.time),
+            op,
+        },
+    ))
+}
+
+/// Finds the smallest non-linearizable prefix of `calls`, if any. Prefixes
+/// end at a completion; linearizability is prefix-closed, so a binary search
+/// over them finds the first one that fails.
+fn counterexample(key: &Value, calls: &[Call]) -> Option<Counterexample> {
+    if search(calls, false).linearizable {
+        return None;
+    }
+    let mut completions: Vec<Duration> = calls.iter().filter_map(Call::completed).collect();
+    completions.sort();
+    completions.dedup();
+    let (mut good, mut bad) = (0, completions.len() - 1);
+    if !search(&prefix(calls, completions[0]), false).linearizable {
+        bad = 0;
+    } else {
+        // `completions[good]` passes and `completions[bad]` fails.
+        while bad - good > 1 {
+            let middle = (good + bad) / 2;
+            if search(&prefix(calls, completions[middle]), false).linearizable {
+                good = middle;
+            } else {
+                bad = middle;
+            }
+        }
+    }
+
+    let end = completions[bad];
+    let index = calls
+        .iter()
+        .position(|call| call.completed() == Some(end))
+        .expect("every prefix ends at a completion");
+    let op = calls[index].clone();
+    let concurrent = calls
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != index)
+        .map(|(_, call)| call)
+        .filter(|call| {
+            call.invoked() <= end
+                && call
+                    .completed()
+                    .is_none_or(|completed| completed >= op.invoked())
+        })
+        .cloned()
+        .collect();
+
+    // The states every complete linearization of the operations that finished
+    // before `op` began can end in.
+    let before: Vec<Call> = calls
+        .iter()
+        .filter(|call| {
+            call.completed()
+                .is_some_and(|completed| completed < op.invoked())
+        })
+        .cloned()
+        .collect();
+    let possible_values = search(&before, true)
+        .final_states
+        .into_iter()
+        .map(|state| state.unwrap_or(Value::Null))
+        .collect();
+
+    Some(Counterexample {
+        key: key.clone(),
+        op,
+        concurrent,
+        possible_values,
+    })
+}
+
+/// The calls that began by `end`, with those still running at `end` turned
+/// into calls with an unknown outcome.
+fn prefix(calls: &[Call], end: Duration) -> Vec<Call> {
+    calls
+        .iter()
+        .filter(|call| call.invoked() <= end)
+        .map(|call| {
+            let mut call = call.clone();
+            if call.completed().is_some_and(|completed| completed > end) {
+                call.complete = None;
+            }
+            call
+        })
+        .filter(|call| call.complete.is_some() || !matches!(call.op, RegisterOp::Read(_)))
+        .collect()
+}
+
+struct Search {
+    linearizable: bool,
+    /// When exploring exhaustively, the states of every linearization that
+    /// took all completed calls.
+    final_states: Vec<Option<Value>>,
+}
+
+/// Wing & Gong's search with Lowe's memoization: depth-first over the order
+/// in which calls take effect, where a call may go next if it began before
+/// every pending call completed, skipping configurations already seen.
+/// Calls that never completed may take effect or not.
+fn search(calls: &[Call], exhaustive: bool) -> Search {
+    let completed: Vec<usize> = (0..calls.len())
+        .filter(|&i| calls[i].complete.is_some())
+        .collect();
+    let words = calls.len().div_ceil(64);
+    let mut seen: HashSet<(Vec<u64>, Option<String>)> = HashSet::new();
+    let mut final_states = BTreeSet::new();
+    let mut stack = vec![(vec![0u64; words], None::<Value>)];
+    let is_done = |done: &[u64], i: usize| done[i / 64] & (1 << (i % 64)) != 0;
+
+    while let Some((done, state)) = stack.pop() {
+        if completed.iter().all(|&i| is_done(&done, i)) {
+            if !exhaustive {
+                return Search {
+                    linearizable: true,
+                    final_states: Vec::new(),
+                };
+            }
+            final_states.insert(state.as_ref().map(Value::to_string));
+        }
+
+        let horizon = (0..calls.len())
+            .filter(|&i| !is_done(&done, i))
+            .filter_map(|i| calls[i].completed())
+            .min();
+        for (i, call) in calls.iter().enumerate() {
+            if is_done(&done, i) || horizon.is_some_and(|horizon| call.invoked() > horizon) {
+                continue;
+            }
+            let Some(next) = call.apply(&state) else {
+                continue;
+            };
+            let mut next_done = done.clone();
+            next_done[i / 64] |= 1 << (i % 64);
+            if seen.insert((next_done.clone(), next.as_ref().map(Value::to_string))) {
+                stack.push((next_done, next));
+            }
+        }
+    }
+
+    Search {
+        linearizable: !final_states.is_empty(),
+        final_states: final_states
+            .into_iter()
+            .map(|state| state.map(|state| serde_json::from_str(&state).expect("states are JSON")))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker::history;
+    use serde_json::json;
+
+    #[test]
+    fn reads_concurrent_with_a_write_may_see_either_value() {
+        for seen in [json!(null), json!(1)] {
+            let analysis = check(&history(&[
+                (0, "invoke", "write", json!(["k", 1]), 0),
+                (1, "invoke", "read", json!(["k", null]), 10),
+                (1, "ok", "read", json!(["k", seen]), 20),
+                (0, "ok", "write", json!(["k", 1]), 30),
+                (1, "invoke", "read", json!(["k", null]), 40),
+                (1, "ok", "read", json!(["k", 1]), 50),
+            ]));
+            assert!(analysis.valid, "{analysis:?}");
+            assert_eq!((analysis.key_count, analysis.op_count), (1, 3));
+        }
+    }
+
+    #[test]
+    fn writes_with_an_unknown_outcome_may_show_up_later() {
+        let analysis = check(&history(&[
+            (0, "invoke", "write", json!(["k", 1]), 0),
+            (0, "ok", "write", json!(["k", 1]), 10),
+            (1, "invoke", "write", json!(["k", 2]), 20),
+            (1, "info", "write", json!(["k", 2]), 30),
+            (0, "invoke", "read", json!(["k", null]), 40),
+            (0, "ok", "read", json!(["k", 1]), 50),
+            (0, "invoke", "read", json!(["k", null]), 60),
+            (0, "ok", "read", json!(["k", 2]), 70),
+        ]));
+        assert!(analysis.valid, "{analysis:?}");
+    }
+
+    #[test]
+    fn failed_cas_does_not_take_effect() {
+        let analysis = check(&history(&[
+            (0, "invoke", "write", json!(["k", 1]), 0),
+            (0, "ok", "write", json!(["k", 1]), 10),
+            (1, "invoke", "cas", json!(["k", [2, 3]]), 20),
+            (1, "fail", "cas", json!(["k", [2, 3]]), 30),
+            (0, "invoke", "read", json!(["k", null]), 40),
+            (0, "ok", "read", json!(["k", 1]), 50),
+        ]));
+        assert!(analysis.valid, "{analysis:?}");
+        assert_eq!(analysis.op_count, 2);
+    }
+
+    #[test]
+    fn cas_from_a_value_the_register_never_held_is_not_linearizable() {
+        let analysis = check(&history(&[
+            (0, "invoke", "write", json!(["k", 1]), 0),
+            (0, "ok", "write", json!(["k", 1]), 10),
+            (1, "invoke", "cas", json!(["k", [2, 3]]), 20),
+            (1, "ok", "cas", json!(["k", [2, 3]]), 30),
+        ]));
+        assert!(!analysis.valid);
+        let counterexample = analysis.counterexample.expect("a counterexample");
+        assert_eq!(counterexample.op.f, "cas");
+        assert_eq!(counterexample.possible_values, vec![json!(1)]);
+    }
+
+    #[test]
+    fn counterexamples_name_the_stale_read_and_what_it_could_have_seen() {
+        let analysis = check(&history(&[
+            (0, "invoke", "write", json!(["k", 1]), 0),
+            (0, "ok", "write", json!(["k", 1]), 10),
+            (0, "invoke", "write", json!(["k", 2]), 20),
+            (0, "ok", "write", json!(["k", 2]), 30),
+            (1, "invoke", "write", json!(["k", 3]), 35),
+            (2, "invoke", "read", json!(["k", null]), 40),
+            (2, "ok", "read", json!(["k", 1]), 50),
+            (1, "ok", "write", json!(["k", 3]), 60),
+        ]));
+        assert!(!analysis.valid);
+        let counterexample = analysis.counterexample.expect("a counterexample");
+        assert_eq!(counterexample.key, json!("k"));
+        let op = &counterexample.op;
+        assert_eq!(
+            (op.f.as_str(), op.invoke, op.complete),
+            ("read", 40, Some(50))
+        );
+        assert_eq!(op.value, json!(["k", 1]));
+        assert_eq!(counterexample.concurrent.len(), 1);
+        assert_eq!(counterexample.concurrent[0].value, json!(["k", 3]));
+        assert_eq!(counterexample.possible_values, vec![json!(2)]);
+    }
+}
@@ -0,0 +1,150 @@
+//! Checkers that decide whether a recorded history is valid for its
+//! workload, in the spirit of Maelstrom's.
+//!
+//! The simulator runs the matching checker after every run. A history
+//! written with `--history` can also be checked on its own:
+//!
+//! ```text
+//! cargo run --bin 3 -- check --workload broadcast --history history.jsonl
+//! ```
+
+pub mod broadcast;
+pub mod g_counter;
+pub mod kafka;
+pub mod linearizable;
+pub mod unique_ids;
+
+use crate::sim::history::History;
+use crate::sim::workload::WorkloadKind;
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// What a checker concluded, with the details that led to it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Verdict {
+    pub valid: bool,
+    pub details: Value,
+}
+
+impl Verdict {
+    fn new(valid: bool, details: impl Serialize) -> Self {
+        Verdict {
+            valid,
+            details: serde_json::to_value(details).expect("analyses always serialize"),
+        }
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "valid: {}", self.valid)?;
+        if let Value::Object(details) = &self.details {
+            for (key, value) in details.iter().filter(|(key, _)| *key != "valid") {
+                write!(f, "\n{key}: {value}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs the checker for `workload` over `history`, if there is one.
+pub fn check(workload: WorkloadKind, history: &History) -> Option<Verdict> {
+    match workload {
+        WorkloadKind::Broadcast => {
+            let analysis = broadcast::check(history);
+            Some(Verdict::new(analysis.valid, analysis))
+        }
+        WorkloadKind::GCounter => {
+            let analysis = g_counter::check(history);
+            Some(Verdict::new(analysis.valid, analysis))
+        }
+        WorkloadKind::Kafka => {
+            let analysis = kafka::check(history);
+            Some(Verdict::new(analysis.valid, analysis))
+        }
+        WorkloadKind::LinKv => {
+            let analysis = linearizable::check(history);
+            Some(Verdict::new(analysis.valid, analysis))
+        }
+        WorkloadKind::UniqueIds => {
+            let analysis = unique_ids::check(history);
+            Some(Verdict::new(analysis.valid, analysis))
+        }
+        WorkloadKind::Echo => None,
+    }
+}
+
+/// Parses the arguments following `check`, `--workload NAME --history PATH`,
+/// checks the history and fails if it is not valid.
+pub fn check_file(args: impl Iterator<Item = String>) -> anyhow::Result<()> {
+    let mut workload = None;
+    let mut path = None;
+    let mut args = args;
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .with_context(|| format!("missing value for `{flag}`"))?;
+        match flag.as_str() {
+            "--workload" => workload = Some(WorkloadKind::parse(&value)?),
+            "--history" => path = Some(PathBuf::from(value)),
+            _ => anyhow::bail!("unknown flag `{flag}`"),
+        }
+    }
+    let workload = workload.context("`--workload` is required")?;
+    let path = path.context("`--history` is required")?;
+
+    let history = History::read(&path)?;
+    let verdict = check(workload, &history)
+        .with_context(|| format!("there is no checker for {}", workload.name()))?;
+    println!("{verdict}");
+    anyhow::ensure!(verdict.valid, "{} is not valid", path.display());
+    Ok(())
+}
+
+/// The `q` quantile of `sorted`, picking the nearest rank.
+pub fn quantile(sorted: &[Duration], q: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    sorted[((sorted.len() - 1) as f64 * q).round() as usize]
+}
+
+/// The quantiles Maelstrom reports for a set of latencies, in milliseconds.
+pub(crate) fn latency_quantiles(mut latencies: Vec<Duration>) -> BTreeMap<String, f64> {
+    latencies.sort();
+    [0.0, 0.5, 0.95, 0.99, 1.0]
+        .into_iter()
+        .map(|q| {
+            (
+                q.to_string(),
+                quantile(&latencies, q).as_secs_f64() * 1000.0,
+            )
+        })
+        .collect()
+}
+
+/// Builds a history out of `(process, type, f, value, time)` entries, with
+/// process `i` talking to node `n{i}`.
+#[cfg(test)]
+pub(crate) fn history(ops: &[(usize, &str, &str, Value, u64)]) -> History {
+    use crate::sim::history::{Op, Process};
+
+    let ops = ops
+        .iter()
+        .map(|(process, kind, f, value, time)| Op {
+            process: Process::Client(*process),
+            kind: serde_json::from_value(Value::from(*kind)).expect("a valid op type"),
+            f: f.to_string(),
+            value: value.clone(),
+            node: format!("n{process}"),
+            time: *time,
+            error: None,
+        })
+        .collect();
+    History { ops }
+}
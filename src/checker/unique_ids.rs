@@ -0,0 +1,136 @@
+use crate::sim::history::{History, Process};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// How many duplicated ids the analysis lists; a node that hands out ids
+/// twice usually does so for every id after some point.
+const DUPLICATES_SHOWN: usize = 16;
+
+/// What the unique-ids checker found. A history is valid when no two
+/// acknowledged `generate` operations returned the same id, whichever nodes
+/// answered them.
+#[derive(Debug, Clone, Serialize)]
+pub struct UniqueIdsAnalysis {
+    pub valid: bool,
+    pub attempted_count: usize,
+    pub acknowledged_count: usize,
+    /// How many distinct ids were handed out.
+    pub unique_count: usize,
+    /// How many ids were handed out more than once.
+    pub duplicated_count: usize,
+    /// The first duplicated ids, with every operation that got them.
+    pub duplicated: BTreeMap<String, Vec<Generated>>,
+    /// The lowest and highest id, if all of them are numbers.
+    pub range: Option<(i64, i64)>,
+}
+
+/// An acknowledged `generate`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Generated {
+    pub process: Process,
+    pub node: String,
+    /// When the operation completed, in nanoseconds since the start of the
+    /// run.
+    pub time: u64,
+}
+
+/// Checks a history of `generate` operations, whose completions carry the
+/// id. Ids may be any JSON value; they are compared as written.
+pub fn check(history: &History) -> UniqueIdsAnalysis {
+    let mut attempted_count = 0;
+    let mut ids: BTreeMap<String, Vec<Generated>> = BTreeMap::new();
+    let mut numbers = Vec::new();
+    for pair in history.pairs() {
+        if pair.invoke.f != "generate" {
+            continue;
+        }
+        attempted_count += 1;
+        let Some(completion) = pair.completion.filter(|_| pair.is_ok()) else {
+            continue;
+        };
+        numbers.push(completion.value.as_i64());
+        ids.entry(completion.value.to_string())
+            .or_default()
+            .push(Generated {
+                process: pair.invoke.process,
+                node: pair.invoke.node.clone(),
+                time: completion.time,
+            });
+    }
+
+    let acknowledged_count = ids.values().map(Vec::len).sum();
+    let duplicated: Vec<(&String, &Vec<Generated>)> =
+        ids.iter().filter(|(_, ops)| ops.len() > 1).collect();
+    let numbers: Option<Vec<i64>> = numbers.into_iter().collect();
+    let range = numbers.and_then(|numbers| Some((*numbers.iter().min()?, *numbers.iter().max()?)));
+
+    UniqueIdsAnalysis {
+        valid: duplicated.is_empty(),
+        attempted_count,
+        acknowledged_count,
+        unique_count: ids.len(),
+        duplicated_count: duplicated.len(),
+        duplicated: duplicated
+            .into_iter()
+            .take(DUPLICATES_SHOWN)
+            .map(|(id, ops)| (id.clone(), ops.clone()))
+            .collect(),
+        range,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker::history;
+    use serde_json::json;
+
+    #[test]
+    fn ids_handed_out_twice_are_duplicates() {
+        let analysis = check(&history(&[
+            (0, "invoke", "generate", json!(null), 0),
+            (0, "ok", "generate", json!(7), 10),
+            (1, "invoke", "generate", json!(null), 0),
+            (1, "ok", "generate", json!(7), 20),
+            (1, "invoke", "generate", json!(null), 30),
+            (1, "ok", "generate", json!(3), 40),
+        ]));
+        assert!(!analysis.valid);
+        assert_eq!((analysis.unique_count, analysis.duplicated_count), (2, 1));
+        let generated = &analysis.duplicated["7"];
+        let nodes: Vec<&str> = generated.iter().map(|op| op.node.as_str()).collect();
+        assert_eq!(nodes, vec!["n0", "n1"]);
+        assert_eq!(analysis.range, Some((3, 7)));
+    }
+
+    #[test]
+    fn ids_that_are_not_numbers_have_no_range() {
+        let analysis = check(&history(&[
+            (0, "invoke", "generate", json!(null), 0),
+            (0, "ok", "generate", json!("a"), 10),
+            (1, "invoke", "generate", json!(null), 0),
+            (1, "ok", "generate", json!(1), 10),
+        ]));
+        assert!(analysis.valid);
+        assert_eq!(analysis.range, None);
+    }
+
+    #[test]
+    fn only_acknowledged_generates_count() {
+        let analysis = check(&history(&[
+            (0, "invoke", "generate", json!(null), 0),
+            (0, "ok", "generate", json!(1), 10),
+            (1, "invoke", "generate", json!(null), 0),
+            (1, "fail", "generate", json!(1), 10),
+            (2, "invoke", "generate", json!(null), 0),
+            (2, "info", "generate", json!(1), 10),
+            (3, "invoke", "generate", json!(null), 0),
+        ]));
+        assert!(analysis.valid);
+        assert_eq!(
+            (analysis.attempted_count, analysis.acknowledged_count),
+            (4, 1)
+        );
+        assert_eq!(analysis.unique_count, 1);
+    }
+}
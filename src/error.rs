@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The error codes Maelstrom defines, see
+/// https://github.com/jepsen-io/maelstrom/blob/main/doc/protocol.md#errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "u32", into = "u32")]
+pub enum ErrorCode {
+    Timeout,
+    NodeNotFound,
+    NotSupported,
+    TemporarilyUnavailable,
+    MalformedRequest,
+    Crash,
+    Abort,
+    KeyDoesNotExist,
+    KeyAlreadyExists,
+    PreconditionFailed,
+    TxnConflict,
+    /// Codes of 1000 and above are free for nodes to use as they see fit.
+    Custom(u32),
+}
+
+impl ErrorCode {
+    /// Whether the operation that failed with this code is known not to have
+    /// taken place. Indefinite errors may or may not have had an effect.
+    pub fn is_definite(self) -> bool {
+        !matches!(
+            self,
+            ErrorCode::Timeout | ErrorCode::Crash | ErrorCode::Custom(_)
+        )
+    }
+}
+
+impl From<u32> for ErrorCode {
+    fn from(code: u32) -> Self {
+        match code {
+            0 => ErrorCode::Timeout,
+            1 => ErrorCode::NodeNotFound,
+            10 => ErrorCode::NotSupported,
+            11 => ErrorCode::TemporarilyUnavailable,
+            12 => ErrorCode::MalformedRequest,
+            13 => ErrorCode::Crash,
+            14 => ErrorCode::Abort,
+            20 => ErrorCode::KeyDoesNotExist,
+            21 => ErrorCode::KeyAlreadyExists,
+            22 => ErrorCode::PreconditionFailed,
+            30 => ErrorCode::TxnConflict,
+            code => ErrorCode::Custom(code),
+        }
+    }
+}
+
+impl From<ErrorCode> for u32 {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::Timeout => 0,
+            ErrorCode::NodeNotFound => 1,
+            ErrorCode::NotSupported => 10,
+            ErrorCode::TemporarilyUnavailable => 11,
+            ErrorCode::MalformedRequest => 12,
+            ErrorCode::Crash => 13,
+            ErrorCode::Abort => 14,
+            ErrorCode::KeyDoesNotExist => 20,
+            ErrorCode::KeyAlreadyExists => 21,
+            ErrorCode::PreconditionFailed => 22,
+            ErrorCode::TxnConflict => 30,
+            ErrorCode::Custom(code) => code,
+        }
+    }
+}
+
+/// The body of a Maelstrom `error` message.
+///
+/// Returning one from [`crate::Node::step`] while handling a request makes the
+/// runtime reply with it instead of stopping the node.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename = "error")]
+pub struct Error {
+    pub code: ErrorCode,
+    #[serde(default)]
+    pub text: String,
+}
+
+impl Error {
+    pub fn new(code: ErrorCode, text: impl Into<String>) -> Self {
+        Error {
+            code,
+            text: text.into(),
+        }
+    }
+
+    pub fn timeout() -> Self {
+        Error::new(ErrorCode::Timeout, "RPC timed out")
+    }
+
+    pub fn not_supported(text: impl Into<String>) -> Self {
+        Error::new(ErrorCode::NotSupported, text)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} ({}): {}",
+            self.code,
+            u32::from(self.code),
+            self.text
+        )
+    }
+}
+
+impl std::error::Error for Error {}
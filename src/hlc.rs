@@ -0,0 +1,221 @@
+use crate::Clock;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::rc::Rc;
+use std::time::UNIX_EPOCH;
+
+/// The body field the runtime piggybacks timestamps on.
+pub const HLC_FIELD: &str = "hlc";
+
+/// A point in hybrid logical time: milliseconds of wall time, and a counter
+/// that orders events within the same millisecond. Timestamps compare by
+/// `wall` first, then by `logical`.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct HlcTimestamp {
+    pub wall: u64,
+    pub logical: u32,
+}
+
+impl HlcTimestamp {
+    /// The least timestamp after this one. Once `logical` runs out, the
+    /// overflow moves into `wall`.
+    fn successor(self) -> Self {
+        match self.logical.checked_add(1) {
+            Some(logical) => HlcTimestamp {
+                wall: self.wall,
+                logical,
+            },
+            None => HlcTimestamp {
+                wall: self.wall.saturating_add(1),
+                logical: 0,
+            },
+        }
+    }
+}
+
+impl fmt::Display for HlcTimestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.wall, self.logical)
+    }
+}
+
+/// A hybrid logical clock (Kulkarni et al.). Its timestamps stay close to
+/// the wall clock, never go backwards and respect causality: anything that
+/// happens after receiving a timestamp gets a greater one.
+///
+/// [`Output`](crate::Output) keeps one per node, stamps every message to
+/// another node with [`Hlc::now`] and feeds the stamps it receives to
+/// [`Hlc::update`], so `output.hlc().now()` is causally ordered after every
+/// message the node has seen.
+pub struct Hlc {
+    clock: Rc<dyn Clock>,
+    latest: HlcTimestamp,
+}
+
+impl Hlc {
+    pub fn new(clock: Rc<dyn Clock>) -> Self {
+        Hlc {
+            clock,
+            latest: HlcTimestamp::default(),
+        }
+    }
+
+    /// Timestamps a local event or a send.
+    pub fn now(&mut self) -> HlcTimestamp {
+        let physical = self.physical();
+        self.latest = if physical > self.latest.wall {
+            HlcTimestamp {
+                wall: physical,
+                logical: 0,
+            }
+        } else {
+            self.latest.successor()
+        };
+        self.latest
+    }
+
+    /// Timestamps the receipt of a message stamped `remote`.
+    pub fn update(&mut self, remote: HlcTimestamp) -> HlcTimestamp {
+        let physical = self.physical();
+        let wall = physical.max(self.latest.wall).max(remote.wall);
+        self.latest = if wall == self.latest.wall || wall == remote.wall {
+            // The greater of the two is the one with the highest `logical`
+            // among those at `wall`.
+            self.latest.max(remote).successor()
+        } else {
+            HlcTimestamp { wall, logical: 0 }
+        };
+        self.latest
+    }
+
+    /// The last timestamp handed out, without advancing the clock.
+    pub fn latest(&self) -> HlcTimestamp {
+        self.latest
+    }
+
+    fn physical(&self) -> u64 {
+        self.clock
+            .system_time()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64)
+    }
+}
+
+impl fmt::Debug for Hlc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hlc").field("latest", &self.latest).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::time::{Duration, Instant, SystemTime};
+
+    /// A wall clock that only moves when told to.
+    struct FakeClock {
+        epoch: Instant,
+        elapsed: Cell<Duration>,
+    }
+
+    impl FakeClock {
+        fn at(millis: u64) -> Rc<Self> {
+            Rc::new(FakeClock {
+                epoch: Instant::now(),
+                elapsed: Cell::new(Duration::from_millis(millis)),
+            })
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.epoch + self.elapsed.get()
+        }
+
+        fn system_time(&self) -> SystemTime {
+            UNIX_EPOCH + self.elapsed.get()
+        }
+    }
+
+    #[test]
+    fn timestamps_increase_while_the_clock_stands_still() {
+        let mut hlc = Hlc::new(FakeClock::at(1_000));
+        let stamps: Vec<HlcTimestamp> = (0..3).map(|_| hlc.now()).collect();
+        assert_eq!(
+            stamps,
+            (0..3)
+                .map(|logical| HlcTimestamp {
+                    wall: 1_000,
+                    logical,
+                })
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(hlc.latest(), stamps[2]);
+    }
+
+    #[test]
+    fn timestamps_follow_the_wall_clock() {
+        let clock = FakeClock::at(1_000);
+        let mut hlc = Hlc::new(clock.clone());
+        hlc.now();
+        hlc.now();
+        clock.elapsed.set(Duration::from_millis(1_005));
+        assert_eq!(
+            hlc.now(),
+            HlcTimestamp {
+                wall: 1_005,
+                logical: 0
+            }
+        );
+    }
+
+    #[test]
+    fn stamps_from_ahead_pull_the_clock_forward() {
+        let mut hlc = Hlc::new(FakeClock::at(1_000));
+        hlc.now();
+        let remote = HlcTimestamp {
+            wall: 2_000,
+            logical: 7,
+        };
+        assert_eq!(
+            hlc.update(remote),
+            HlcTimestamp {
+                wall: 2_000,
+                logical: 8
+            }
+        );
+        assert!(hlc.now() > remote);
+    }
+
+    #[test]
+    fn stamps_from_behind_still_advance_the_clock() {
+        let mut hlc = Hlc::new(FakeClock::at(1_000));
+        let local = hlc.now();
+        let updated = hlc.update(HlcTimestamp {
+            wall: 10,
+            logical: 3,
+        });
+        assert!(updated > local);
+    }
+
+    #[test]
+    fn logical_overflow_moves_into_wall() {
+        let mut hlc = Hlc::new(FakeClock::at(1_000));
+        let remote = HlcTimestamp {
+            wall: 2_000,
+            logical: u32::MAX,
+        };
+        let updated = hlc.update(remote);
+        assert_eq!(
+            updated,
+            HlcTimestamp {
+                wall: 2_001,
+                logical: 0
+            }
+        );
+        assert!(updated > remote);
+    }
+}
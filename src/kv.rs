@@ -0,0 +1,245 @@
+use crate::{Error, ErrorCode, Message, Output, RetryPolicy};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+/// The key/value services Maelstrom runs next to the nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Service {
+    /// Sequentially consistent.
+    SeqKv,
+    /// Linearizable.
+    LinKv,
+    /// Last-write-wins, i.e. eventually consistent.
+    LwwKv,
+}
+
+impl Service {
+    /// The node id requests to this service are addressed to.
+    pub fn node_id(self) -> &'static str {
+        match self {
+            Service::SeqKv => "seq-kv",
+            Service::LinKv => "lin-kv",
+            Service::LwwKv => "lww-kv",
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum KvRequest<K, V> {
+    Read {
+        key: K,
+    },
+    Write {
+        key: K,
+        value: V,
+    },
+    Cas {
+        key: K,
+        from: V,
+        to: V,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        create_if_not_exists: bool,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+#[allow(clippy::enum_variant_names)]
+enum KvResponse<V> {
+    ReadOk { value: V },
+    WriteOk,
+    CasOk,
+}
+
+/// Why a key/value operation failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KvError {
+    KeyDoesNotExist,
+    /// A `cas` found a value other than `from` under the key.
+    PreconditionFailed,
+    Other(Error),
+}
+
+impl From<Error> for KvError {
+    fn from(error: Error) -> Self {
+        match error.code {
+            ErrorCode::KeyDoesNotExist => KvError::KeyDoesNotExist,
+            ErrorCode::PreconditionFailed => KvError::PreconditionFailed,
+            _ => KvError::Other(error),
+        }
+    }
+}
+
+impl fmt::Display for KvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KvError::KeyDoesNotExist => write!(f, "key does not exist"),
+            KvError::PreconditionFailed => write!(f, "precondition failed"),
+            KvError::Other(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for KvError {}
+
+/// Typed access to one of Maelstrom's key/value services. Every operation is
+/// an RPC whose outcome is handed to a callback, like [`Output::rpc`].
+#[derive(Debug, Clone, Copy)]
+pub struct KvClient {
+    service: Service,
+    timeout: Option<Duration>,
+}
+
+impl KvClient {
+    pub fn new(service: Service) -> Self {
+        KvClient {
+            service,
+            timeout: None,
+        }
+    }
+
+    /// Fails operations that got no reply within `timeout` with
+    /// [`ErrorCode::Timeout`]. Requests are never resent, since a `cas` that
+    /// did go through would fail on a second attempt.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        KvClient {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    pub fn service(&self) -> Service {
+        self.service
+    }
+
+    pub fn read<N, K, V, F>(
+        &self,
+        output: &mut Output<N>,
+        key: K,
+        callback: F,
+    ) -> anyhow::Result<()>
+    where
+        K: Serialize,
+        V: DeserializeOwned,
+        F: FnOnce(&mut N, Result<V, KvError>, &mut Output<N>) -> anyhow::Result<()> + 'static,
+    {
+        self.call(
+            output,
+            KvRequest::<K, ()>::Read { key },
+            move |node, reply: Result<KvResponse<V>, KvError>, output| {
+                let value = reply.and_then(|reply| match reply {
+                    KvResponse::ReadOk { value } => Ok(value),
+                    _ => Err(unexpected_reply("read")),
+                });
+                callback(node, value, output)
+            },
+        )
+    }
+
+    pub fn write<N, K, V, F>(
+        &self,
+        output: &mut Output<N>,
+        key: K,
+        value: V,
+        callback: F,
+    ) -> anyhow::Result<()>
+    where
+        K: Serialize,
+        V: Serialize,
+        F: FnOnce(&mut N, Result<(), KvError>, &mut Output<N>) -> anyhow::Result<()> + 'static,
+    {
+        self.call(
+            output,
+            KvRequest::Write { key, value },
+            move |node, reply: Result<KvResponse<()>, KvError>, output| {
+                let reply = reply.and_then(|reply| match reply {
+                    KvResponse::WriteOk => Ok(()),
+                    _ => Err(unexpected_reply("write")),
+                });
+                callback(node, reply, output)
+            },
+        )
+    }
+
+    /// Replaces the value under `key` with `to` if it currently is `from`.
+    /// With `create_if_not_exists` a missing key is created holding `to`
+    /// instead of failing with [`KvError::KeyDoesNotExist`].
+    pub fn cas<N, K, V, F>(
+        &self,
+        output: &mut Output<N>,
+        key: K,
+        from: V,
+        to: V,
+        create_if_not_exists: bool,
+        callback: F,
+    ) -> anyhow::Result<()>
+    where
+        K: Serialize,
+        V: Serialize,
+        F: FnOnce(&mut N, Result<(), KvError>, &mut Output<N>) -> anyhow::Result<()> + 'static,
+    {
+        self.call(
+            output,
+            KvRequest::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            },
+            move |node, reply: Result<KvResponse<()>, KvError>, output| {
+                let reply = reply.and_then(|reply| match reply {
+                    KvResponse::CasOk => Ok(()),
+                    _ => Err(unexpected_reply("cas")),
+                });
+                callback(node, reply, output)
+            },
+        )
+    }
+
+    fn call<N, Req, V, F>(
+        &self,
+        output: &mut Output<N>,
+        request: Req,
+        callback: F,
+    ) -> anyhow::Result<()>
+    where
+        Req: Serialize,
+        V: DeserializeOwned,
+        F: FnOnce(&mut N, Result<KvResponse<V>, KvError>, &mut Output<N>) -> anyhow::Result<()>
+            + 'static,
+    {
+        let callback = move |node: &mut N,
+                             reply: Result<Message<KvResponse<V>>, Error>,
+                             output: &mut Output<N>| {
+            callback(
+                node,
+                reply.map(|reply| reply.body.payload).map_err(KvError::from),
+                output,
+            )
+        };
+        let dest = self.service.node_id();
+        match self.timeout {
+            Some(timeout) => output.rpc_with_deadline(
+                dest,
+                request,
+                timeout,
+                RetryPolicy::fixed(timeout).max_attempts(1),
+                callback,
+            ),
+            None => output.rpc(dest, request, callback),
+        }?;
+        Ok(())
+    }
+}
+
+fn unexpected_reply(operation: &str) -> KvError {
+    KvError::Other(Error::new(
+        ErrorCode::MalformedRequest,
+        format!("unexpected reply to {operation}"),
+    ))
+}
@@ -1,15 +1,42 @@
 use anyhow::Context;
+use serde::de::value::MapDeserializer;
+use serde::de::{self, DeserializeOwned};
 use serde::{Deserialize, Serialize};
-use std::io::{stdout, Write};
+use serde_json::Value;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-#[derive(Serialize, Deserialize, Debug)]
+pub mod batch;
+pub mod checker;
+mod error;
+pub mod hlc;
+pub mod kv;
+pub mod rng;
+pub mod sim;
+pub mod tso;
+
+pub use batch::{BatchConfig, Batcher, BATCH_RETRY};
+pub use error::{Error, ErrorCode};
+pub use hlc::{Hlc, HlcTimestamp};
+pub use kv::{KvClient, KvError, Service};
+pub use rng::Rng;
+pub use tso::TsoClient;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Message<P> {
     pub src: String,
     pub dest: String,
     pub body: Body<P>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Body<P> {
     #[serde(flatten)]
     pub payload: P,
@@ -19,23 +46,973 @@ pub struct Body<P> {
     pub msg_id: Option<usize>,
 }
 
-#[derive(Deserialize)]
+impl<P> Message<P> {
+    /// Builds the reply to this message: addresses are swapped, `in_reply_to`
+    /// is set to this message's `msg_id` and the reply gets a fresh `msg_id`
+    /// from `output`.
+    pub fn reply<Q, N>(&self, payload: Q, output: &Output<N>) -> Message<Q> {
+        Message {
+            src: self.dest.clone(),
+            dest: self.src.clone(),
+            body: Body {
+                payload,
+                in_reply_to: self.body.msg_id,
+                msg_id: Some(output.next_msg_id()),
+            },
+        }
+    }
+
+    /// Like [`Message::reply`], but reuses this message's addresses.
+    pub fn into_reply<Q, N>(self, payload: Q, output: &Output<N>) -> Message<Q> {
+        Message {
+            src: self.dest,
+            dest: self.src,
+            body: Body {
+                payload,
+                in_reply_to: self.body.msg_id,
+                msg_id: Some(output.next_msg_id()),
+            },
+        }
+    }
+}
+
+impl Message<Value> {
+    /// Interprets the untyped payload of a message read off the wire as `P`.
+    fn decode<P>(self) -> anyhow::Result<Message<P>>
+    where
+        P: DeserializeOwned,
+    {
+        Ok(Message {
+            src: self.src,
+            dest: self.dest,
+            body: Body {
+                payload: serde_json::from_value(self.body.payload)?,
+                in_reply_to: self.body.in_reply_to,
+                msg_id: self.body.msg_id,
+            },
+        })
+    }
+}
+
+/// Whether `kind` is none of the `type`s the internally tagged payload `P`
+/// knows. Rather than matching on the text of a decode error, this decodes a
+/// bare `{"type": kind}` and watches for serde reporting an unknown variant.
+fn is_unknown_type<P>(kind: &str) -> bool
+where
+    P: DeserializeOwned,
+{
+    let probe = MapDeserializer::<_, TypeProbe>::new(std::iter::once(("type", kind)));
+    matches!(P::deserialize(probe), Err(TypeProbe::UnknownVariant))
+}
+
+/// The error of [`is_unknown_type`], which only tells unknown variants apart
+/// from everything else, e.g. the fields the probe leaves out.
+#[derive(Debug)]
+enum TypeProbe {
+    UnknownVariant,
+    Other,
+}
+
+impl fmt::Display for TypeProbe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeProbe::UnknownVariant => f.write_str("unknown variant"),
+            TypeProbe::Other => f.write_str("not an unknown variant"),
+        }
+    }
+}
+
+impl std::error::Error for TypeProbe {}
+
+impl de::Error for TypeProbe {
+    fn custom<M: fmt::Display>(_msg: M) -> Self {
+        TypeProbe::Other
+    }
+
+    fn unknown_variant(_variant: &str, _expected: &'static [&'static str]) -> Self {
+        TypeProbe::UnknownVariant
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Init {
     pub node_id: String,
     pub node_ids: Vec<String>,
 }
 
-#[derive(Serialize, Debug)]
-#[serde(rename_all = "snake_case")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type")]
-pub struct init_ok {}
+#[serde(rename_all = "snake_case")]
+pub enum InitPayload {
+    Init(Init),
+    InitOk,
+}
+
+/// Everything a node can be asked to react to: messages read from stdin,
+/// ticks of the timers it registered and events injected by other threads.
+#[derive(Debug, Clone)]
+pub enum Event<P, T = ()> {
+    Message(Message<P>),
+    Tick(T),
+    Eof,
+}
+
+/// A challenge state machine. `S` is the initial state handed to [`run`], `P`
+/// is the payload type of the messages the node understands and `T` is the
+/// type of its timer ticks.
+pub trait Node<S, P, T = ()>: Sized {
+    fn from_init(state: S, init: Init, scheduler: &mut Scheduler<P, T>) -> anyhow::Result<Self>;
+
+    fn step(&mut self, input: Event<P, T>, output: &mut Output<Self>) -> anyhow::Result<()>;
+}
+
+/// What the event loop receives: either a raw line from stdin, which may still
+/// turn out to be the reply to an outstanding RPC, or an event for the node.
+enum Input<P, T> {
+    Wire(Message<Value>),
+    Event(Event<P, T>),
+}
+
+/// Sends events into a running node's event loop from any thread.
+pub struct Injector<P, T>(Sender<Input<P, T>>);
+
+impl<P, T> Clone for Injector<P, T> {
+    fn clone(&self) -> Self {
+        Injector(self.0.clone())
+    }
+}
+
+impl<P, T> Injector<P, T> {
+    pub fn send(&self, event: Event<P, T>) -> anyhow::Result<()> {
+        self.0
+            .send(Input::Event(event))
+            .map_err(|_| anyhow::anyhow!("event loop has shut down"))
+    }
+}
+
+/// Handed to [`Node::from_init`] to register periodic timers and to get hold
+/// of an [`Injector`] for feeding events into the node's event loop.
+pub struct Scheduler<P, T> {
+    tx: Sender<Input<P, T>>,
+    timers: Vec<(Duration, T)>,
+    traces: Vec<(String, Value)>,
+}
+
+impl<P, T> Scheduler<P, T> {
+    /// Delivers `Event::Tick(tick)` to the node every `period`.
+    pub fn every(&mut self, period: Duration, tick: T) {
+        self.timers.push((period, tick));
+    }
+
+    /// Like [`Output::trace`], for what the node decides while it starts.
+    pub fn trace(&mut self, f: &str, value: impl Serialize) {
+        self.traces.push((f.to_string(), trace_value(value)));
+    }
+
+    pub fn injector(&self) -> Injector<P, T> {
+        Injector(self.tx.clone())
+    }
+}
+
+/// Where the runtime reads the time from. [`run`] uses [`SystemClock`]; the
+/// simulator substitutes a virtual clock so that timers and RPC deadlines fire
+/// in simulated time.
+pub trait Clock {
+    fn now(&self) -> Instant;
+
+    /// The wall-clock time matching [`Clock::now`].
+    fn system_time(&self) -> SystemTime;
+}
+
+/// The wall clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+struct Timer<T> {
+    period: Duration,
+    next: Instant,
+    tick: T,
+}
+
+/// The periodic timers a node registered with [`Scheduler::every`].
+struct Timers<T>(Vec<Timer<T>>);
+
+impl<T: Clone> Timers<T> {
+    fn new(timers: Vec<(Duration, T)>, now: Instant) -> Self {
+        Timers(
+            timers
+                .into_iter()
+                .map(|(period, tick)| Timer {
+                    period,
+                    next: now + period,
+                    tick,
+                })
+                .collect(),
+        )
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.0.iter().map(|timer| timer.next).min()
+    }
+
+    /// Takes the ticks that are due at `now` and schedules their next firing
+    /// one period later. Missed ticks are not made up for.
+    fn due(&mut self, now: Instant) -> Vec<T> {
+        self.0
+            .iter_mut()
+            .filter(|timer| timer.next <= now)
+            .map(|timer| {
+                timer.next = now + timer.period;
+                timer.tick.clone()
+            })
+            .collect()
+    }
+}
+
+/// How long to wait between attempts of an RPC.
+#[derive(Debug, Clone, Copy)]
+pub enum Backoff {
+    Fixed(Duration),
+    /// Doubles the wait after every attempt, up to `max`.
+    Exponential {
+        initial: Duration,
+        max: Duration,
+    },
+}
+
+/// When and how often an unanswered RPC is sent again.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub backoff: Backoff,
+    /// Total number of sends, including the first one. `None` keeps retrying
+    /// until the deadline passes.
+    pub max_attempts: Option<u32>,
+}
+
+impl RetryPolicy {
+    pub const fn fixed(interval: Duration) -> Self {
+        RetryPolicy {
+            backoff: Backoff::Fixed(interval),
+            max_attempts: None,
+        }
+    }
+
+    pub const fn exponential(initial: Duration, max: Duration) -> Self {
+        RetryPolicy {
+            backoff: Backoff::Exponential { initial, max },
+            max_attempts: None,
+        }
+    }
+
+    pub const fn max_attempts(self, max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts: Some(max_attempts),
+            ..self
+        }
+    }
+
+    fn initial_interval(&self) -> Duration {
+        match self.backoff {
+            Backoff::Fixed(interval) => interval,
+            Backoff::Exponential { initial, .. } => initial,
+        }
+    }
+
+    fn next_interval(&self, interval: Duration) -> Duration {
+        match self.backoff {
+            Backoff::Fixed(interval) => interval,
+            Backoff::Exponential { max, .. } => (interval * 2).min(max),
+        }
+    }
+}
+
+/// Invoked with the node, the reply to an RPC (or the error it failed with) and
+/// the output it arrived on.
+pub type Callback<N> =
+    Box<dyn FnOnce(&mut N, Result<Message<Value>, Error>, &mut Output<N>) -> anyhow::Result<()>>;
+
+struct Pending<N> {
+    callback: Callback<N>,
+    deadline: Option<Instant>,
+    retry: Option<Retry>,
+}
+
+/// Retransmission state of an outstanding RPC.
+struct Retry {
+    request: Message<Value>,
+    policy: RetryPolicy,
+    attempts: u32,
+    interval: Duration,
+    next_attempt: Instant,
+}
+
+/// Sink for outgoing messages; every message is written as one line of JSON.
+///
+/// It also owns the node's `msg_id` allocator and the table of outstanding
+/// RPCs, so replies can be routed back to whoever sent the request, and the
+/// node's hybrid logical clock.
+pub struct Output<N> {
+    writer: Box<dyn Write>,
+    node_id: String,
+    next_msg_id: Cell<usize>,
+    pending: HashMap<usize, Pending<N>>,
+    clock: Rc<dyn Clock>,
+    hlc: Hlc,
+    /// The nodes whose messages carry [`Hlc`] timestamps.
+    peers: HashSet<String>,
+    /// Traces kept for the simulator; `None` logs them to stderr instead.
+    traces: Option<Vec<(String, Value)>>,
+}
+
+/// Where a node starts numbering its messages: the microseconds since the
+/// Unix epoch at boot. A restarted node then never reuses a `msg_id` of its
+/// previous run, so a late reply to that run cannot be mistaken for the reply
+/// to a new request, unless the old run sent more than a million messages a
+/// second.
+fn first_msg_id(clock: &dyn Clock) -> usize {
+    clock
+        .system_time()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_micros() as usize)
+}
+
+impl<N> Output<N> {
+    pub fn new(node_id: String, writer: Box<dyn Write>) -> Self {
+        let clock: Rc<dyn Clock> = Rc::new(SystemClock);
+        Output {
+            writer,
+            node_id,
+            next_msg_id: Cell::new(first_msg_id(clock.as_ref())),
+            pending: HashMap::new(),
+            hlc: Hlc::new(clock.clone()),
+            clock,
+            peers: HashSet::new(),
+            traces: None,
+        }
+    }
+
+    /// Replaces the wall clock that RPC deadlines and the hybrid logical
+    /// clock are measured against.
+    pub fn with_clock(self, clock: Rc<dyn Clock>) -> Self {
+        Output {
+            next_msg_id: Cell::new(first_msg_id(clock.as_ref())),
+            hlc: Hlc::new(clock.clone()),
+            clock,
+            ..self
+        }
+    }
+
+    /// Stamps messages to `peers` with the hybrid logical clock, in the
+    /// body's [`hlc::HLC_FIELD`], and merges the stamps of messages from them
+    /// into it. Clients and services never see the field.
+    pub fn with_peers(self, peers: impl IntoIterator<Item = String>) -> Self {
+        Output {
+            peers: peers.into_iter().collect(),
+            ..self
+        }
+    }
+
+    /// Keeps what the node traces, for the simulator to record in its
+    /// history, rather than logging it.
+    pub fn with_traces(self) -> Self {
+        Output {
+            traces: Some(Vec::new()),
+            ..self
+        }
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// Notes something about the node itself that is worth seeing next to
+    /// the operations, e.g. the neighbors it picked. It is logged to stderr,
+    /// and the simulator records it in the history as an `info` op `f` of
+    /// the node.
+    pub fn trace(&mut self, f: &str, value: impl Serialize) {
+        let value = trace_value(value);
+        match &mut self.traces {
+            Some(traces) => traces.push((f.to_string(), value)),
+            None => eprintln!("{} {f}: {value}", self.node_id),
+        }
+    }
+
+    /// Takes what the node traced since the last call.
+    pub(crate) fn take_traces(&mut self) -> Vec<(String, Value)> {
+        self.traces.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// The current time of the clock the node runs on. Nodes should use this
+    /// rather than `Instant::now()` so they behave the same in the simulator.
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// The wall-clock time of the clock the node runs on, for anything that
+    /// ends up in a timestamp; use it instead of `SystemTime::now()`.
+    pub fn system_time(&self) -> SystemTime {
+        self.clock.system_time()
+    }
+
+    /// The node's hybrid logical clock, which already accounts for every
+    /// message received so far.
+    pub fn hlc(&mut self) -> &mut Hlc {
+        &mut self.hlc
+    }
+
+    /// Hands out the next `msg_id` of this node; ids are never reused.
+    pub fn next_msg_id(&self) -> usize {
+        let id = self.next_msg_id.get();
+        self.next_msg_id.set(id + 1);
+        id
+    }
+
+    pub fn send<P>(&mut self, message: &Message<P>) -> anyhow::Result<()>
+    where
+        P: Serialize,
+    {
+        if self.peers.contains(&message.dest) {
+            let mut message = serde_json::to_value(message).context("Can not serialize")?;
+            message["body"][hlc::HLC_FIELD] = serde_json::to_value(self.hlc.now())?;
+            serde_json::to_writer(&mut *self.writer, &message)
+        } else {
+            serde_json::to_writer(&mut *self.writer, message)
+        }
+        .context("Can not serialize")?;
+        self.writer
+            .write_all(b"\n")
+            .context("writing trailing new line")?;
+        self.writer.flush().context("flushing output")
+    }
+
+    /// Sends `payload` to `dest` as a request with a fresh `msg_id` and
+    /// arranges for `callback` to be called with the reply whose
+    /// `in_reply_to` matches it. Waits for the reply forever and never
+    /// resends. Returns the allocated `msg_id`.
+    pub fn rpc<Req, Resp, F>(
+        &mut self,
+        dest: impl Into<String>,
+        payload: Req,
+        callback: F,
+    ) -> anyhow::Result<usize>
+    where
+        Req: Serialize,
+        Resp: DeserializeOwned,
+        F: FnOnce(&mut N, Result<Message<Resp>, Error>, &mut Output<N>) -> anyhow::Result<()>
+            + 'static,
+    {
+        let (msg_id, _) = self.send_request(dest.into(), payload)?;
+        self.pending.insert(
+            msg_id,
+            Pending {
+                callback: typed_callback(callback),
+                deadline: None,
+                retry: None,
+            },
+        );
+        Ok(msg_id)
+    }
+
+    /// Like [`Output::rpc`], but resends the request according to `retry`
+    /// while no reply has arrived and gives up with [`ErrorCode::Timeout`] once
+    /// `timeout` has passed since the first send.
+    pub fn rpc_with_deadline<Req, Resp, F>(
+        &mut self,
+        dest: impl Into<String>,
+        payload: Req,
+        timeout: Duration,
+        retry: RetryPolicy,
+        callback: F,
+    ) -> anyhow::Result<usize>
+    where
+        Req: Serialize,
+        Resp: DeserializeOwned,
+        F: FnOnce(&mut N, Result<Message<Resp>, Error>, &mut Output<N>) -> anyhow::Result<()>
+            + 'static,
+    {
+        let (msg_id, request) = self.send_request(dest.into(), payload)?;
+        let now = self.now();
+        let interval = retry.initial_interval();
+        self.pending.insert(
+            msg_id,
+            Pending {
+                callback: typed_callback(callback),
+                deadline: Some(now + timeout),
+                retry: Some(Retry {
+                    request,
+                    policy: retry,
+                    attempts: 1,
+                    interval,
+                    next_attempt: now + interval,
+                }),
+            },
+        );
+        Ok(msg_id)
+    }
+
+    fn send_request<Req>(
+        &mut self,
+        dest: String,
+        payload: Req,
+    ) -> anyhow::Result<(usize, Message<Value>)>
+    where
+        Req: Serialize,
+    {
+        let msg_id = self.next_msg_id();
+        let request = Message {
+            src: self.node_id.clone(),
+            dest,
+            body: Body {
+                payload: serde_json::to_value(payload).context("Can not serialize")?,
+                in_reply_to: None,
+                msg_id: Some(msg_id),
+            },
+        };
+        self.send(&request)?;
+        Ok((msg_id, request))
+    }
+
+    /// The earliest moment at which an outstanding RPC needs to be resent or
+    /// timed out.
+    fn next_wakeup(&self) -> Option<Instant> {
+        self.pending
+            .values()
+            .flat_map(|pending| {
+                let resend = pending
+                    .retry
+                    .as_ref()
+                    .filter(|retry| !retry.exhausted())
+                    .map(|retry| retry.next_attempt);
+                pending.deadline.into_iter().chain(resend)
+            })
+            .min()
+    }
+
+    /// Resends RPCs whose retry interval has elapsed and fails those whose
+    /// deadline has passed, both in `msg_id` order so that a simulated run
+    /// does not depend on the iteration order of the pending table.
+    fn expire(&mut self, node: &mut N) -> anyhow::Result<()> {
+        let now = self.now();
+        let mut expired: Vec<usize> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.deadline.is_some_and(|deadline| deadline <= now))
+            .map(|(&msg_id, _)| msg_id)
+            .collect();
+        expired.sort_unstable();
+        for msg_id in expired {
+            if let Some(pending) = self.pending.remove(&msg_id) {
+                (pending.callback)(node, Err(Error::timeout()), self)?;
+            }
+        }
+
+        let mut resend = Vec::new();
+        for pending in self.pending.values_mut() {
+            let Some(retry) = pending.retry.as_mut() else {
+                continue;
+            };
+            if retry.exhausted() || retry.next_attempt > now {
+                continue;
+            }
+            retry.attempts += 1;
+            retry.interval = retry.policy.next_interval(retry.interval);
+            retry.next_attempt = now + retry.interval;
+            resend.push(retry.request.clone());
+        }
+        resend.sort_by_key(|request| request.body.msg_id);
+        for request in resend {
+            self.send(&request)?;
+        }
+        Ok(())
+    }
+
+    fn process<S, P, T>(&mut self, node: &mut N, input: Input<P, T>) -> anyhow::Result<()>
+    where
+        N: Node<S, P, T>,
+        P: DeserializeOwned,
+    {
+        match input {
+            Input::Wire(message) => self.deliver(node, message),
+            Input::Event(event) => node.step(event, self),
+        }
+    }
+
+    /// Routes a message read off the wire either to the callback of the RPC it
+    /// answers or, failing that, to the node's `step`.
+    fn deliver<S, P, T>(&mut self, node: &mut N, message: Message<Value>) -> anyhow::Result<()>
+    where
+        N: Node<S, P, T>,
+        P: DeserializeOwned,
+    {
+        if self.peers.contains(&message.src) {
+            let stamp = message.body.payload.get(hlc::HLC_FIELD).cloned();
+            if let Some(remote) = stamp.and_then(|stamp| serde_json::from_value(stamp).ok()) {
+                self.hlc.update(remote);
+            }
+        }
+
+        let in_reply_to = message.body.in_reply_to;
+        if let Some(pending) = in_reply_to.and_then(|id| self.pending.remove(&id)) {
+            return (pending.callback)(node, Ok(message), self);
+        }
+
+        let request = Message {
+            src: message.src.clone(),
+            dest: message.dest.clone(),
+            body: Body {
+                payload: (),
+                in_reply_to,
+                msg_id: message.body.msg_id,
+            },
+        };
+        let kind = message.body.payload.get("type").and_then(Value::as_str);
+        let kind = kind.map(str::to_owned);
+        let result = match message.decode() {
+            Ok(message) => node.step(Event::Message(message), self),
+            // A duplicate reply to an RPC that has already completed, e.g.
+            // because the request was resent.
+            Err(_) if in_reply_to.is_some() => return Ok(()),
+            Err(err) => match kind.filter(|kind| is_unknown_type::<P>(kind)) {
+                Some(kind) => {
+                    Err(Error::not_supported(format!("unknown message type `{kind}`")).into())
+                }
+                None => Err(Error::new(ErrorCode::MalformedRequest, err.to_string()).into()),
+            },
+        };
+        let error = match result {
+            Ok(()) => return Ok(()),
+            Err(err) => err.downcast::<Error>()?,
+        };
+        match request.body.msg_id {
+            Some(_) => self.send(&request.into_reply(error, self)),
+            None => {
+                eprintln!(
+                    "dropping message from {} that failed with {error}",
+                    request.src
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A booted node together with everything that drives it: its output, its
+/// timers and the channel other threads inject events through.
+pub(crate) struct Runtime<S, N, P, T> {
+    node: N,
+    output: Output<N>,
+    timers: Timers<T>,
+    tx: Sender<Input<P, T>>,
+    rx: Receiver<Input<P, T>>,
+    _state: PhantomData<fn(S)>,
+}
+
+impl<S, N, P, T> Runtime<S, N, P, T>
+where
+    N: Node<S, P, T>,
+    P: DeserializeOwned,
+    T: Clone,
+{
+    /// Builds a node from its `init` message and acknowledges the message on
+    /// `writer`. Timers and RPC deadlines are measured against `clock`.
+    pub(crate) fn boot(
+        init_state: S,
+        init_msg: Message<InitPayload>,
+        writer: Box<dyn Write>,
+        clock: Rc<dyn Clock>,
+        keep_traces: bool,
+    ) -> anyhow::Result<Self> {
+        let InitPayload::Init(init) = init_msg.body.payload.clone() else {
+            anyhow::bail!("first message should be init");
+        };
+        let mut output = Output::new(init.node_id.clone(), writer)
+            .with_clock(clock)
+            .with_peers(init.node_ids.clone());
+        if keep_traces {
+            output = output.with_traces();
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let mut scheduler = Scheduler {
+            tx: tx.clone(),
+            timers: Vec::new(),
+            traces: Vec::new(),
+        };
+        let node =
+            N::from_init(init_state, init, &mut scheduler).context("node initialization failed")?;
+        for (f, value) in scheduler.traces {
+            output.trace(&f, value);
+        }
+
+        output.send(&init_msg.into_reply(InitPayload::InitOk, &output))?;
+        Ok(Runtime {
+            node,
+            timers: Timers::new(scheduler.timers, output.now()),
+            output,
+            tx,
+            rx,
+            _state: PhantomData,
+        })
+    }
+
+    /// The earliest moment a timer or an outstanding RPC needs attention.
+    pub(crate) fn next_wakeup(&self) -> Option<Instant> {
+        self.timers
+            .next_due()
+            .into_iter()
+            .chain(self.output.next_wakeup())
+            .min()
+    }
+
+    /// Fires the timers and handles the RPC deadlines that are due.
+    pub(crate) fn wake(&mut self) -> anyhow::Result<()> {
+        for tick in self.timers.due(self.output.now()) {
+            self.node.step(Event::Tick(tick), &mut self.output)?;
+        }
+        self.output.expire(&mut self.node)
+    }
+
+    pub(crate) fn deliver(&mut self, message: Message<Value>) -> anyhow::Result<()> {
+        self.output.deliver(&mut self.node, message)
+    }
+
+    fn process(&mut self, input: Input<P, T>) -> anyhow::Result<()> {
+        self.output.process(&mut self.node, input)
+    }
+
+    pub(crate) fn take_traces(&mut self) -> Vec<(String, Value)> {
+        self.output.take_traces()
+    }
 
-pub fn respond<P>(message: Message<P>)
+    /// Handles the events other threads injected so far without waiting for
+    /// more.
+    pub(crate) fn drain_injected(&mut self) -> anyhow::Result<()> {
+        while let Ok(input) = self.rx.try_recv() {
+            self.process(input)?;
+        }
+        Ok(())
+    }
+}
+
+fn trace_value(value: impl Serialize) -> Value {
+    serde_json::to_value(value).unwrap_or_else(|err| Value::String(err.to_string()))
+}
+
+/// Reads the environment variable `name` through `parse`, for nodes that
+/// take their configuration from the environment. `None` if it is unset.
+pub fn env_var<T>(
+    name: &str,
+    parse: impl FnOnce(&str) -> anyhow::Result<T>,
+) -> anyhow::Result<Option<T>> {
+    match std::env::var(name) {
+        Ok(value) => parse(&value)
+            .map(Some)
+            .with_context(|| format!("invalid `{name}`")),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(error) => Err(error).with_context(|| format!("can not read `{name}`")),
+    }
+}
+
+/// Performs the `init` handshake on stdin/stdout and then feeds every
+/// following message, timer tick and injected event to the node from a single
+/// loop, so node state never leaves the main thread. Timers and RPC deadlines
+/// are kept by the loop itself rather than by sleeping threads.
+///
+/// When the binary is started as `<binary> simulate [options]` the node is
+/// instead run in the local simulator, see [`sim::SimConfig::from_args`], and
+/// `<binary> check [options]` checks a history it wrote, see
+/// [`checker::check_file`].
+pub fn run<S, N, P, T>(init_state: S) -> anyhow::Result<()>
 where
-    P: Serialize,
+    S: Clone + 'static,
+    N: Node<S, P, T> + 'static,
+    P: DeserializeOwned + Send + 'static,
+    T: Clone + Send + 'static,
 {
-    serde_json::to_writer(stdout().lock(), &message)
-        .context("Can not serialize")
-        .unwrap();
-    stdout().lock().write_all(b"\n").unwrap();
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        None => {}
+        Some("simulate") => {
+            return sim::simulate::<S, N, P, T>(init_state, sim::SimConfig::from_args(args)?)
+        }
+        Some("check") => return checker::check_file(args),
+        Some(arg) => {
+            anyhow::bail!("unknown argument `{arg}`, expected `simulate`, `check` or nothing")
+        }
+    }
+
+    let mut init_line = String::new();
+    std::io::stdin()
+        .read_line(&mut init_line)
+        .context("Failed to read init message from STDIN")?;
+    let init_msg: Message<InitPayload> =
+        serde_json::from_str(&init_line).context("Failed to parse INIT message")?;
+    let clock = Rc::new(SystemClock);
+    let mut runtime = Runtime::<S, N, P, T>::boot(
+        init_state,
+        init_msg,
+        Box::new(std::io::stdout().lock()),
+        clock.clone(),
+        false,
+    )?;
+
+    let tx = runtime.tx.clone();
+    let reader = thread::spawn(move || {
+        let result = read_stdin(&tx);
+        let _ = tx.send(Input::Event(Event::Eof));
+        result
+    });
+
+    loop {
+        let input = match runtime.next_wakeup() {
+            Some(wakeup) => {
+                match runtime
+                    .rx
+                    .recv_timeout(wakeup.saturating_duration_since(clock.now()))
+                {
+                    Ok(input) => Some(input),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            None => match runtime.rx.recv() {
+                Ok(input) => Some(input),
+                Err(_) => break,
+            },
+        };
+        runtime.wake().context("Node step function failed")?;
+        let Some(input) = input else {
+            continue;
+        };
+        let eof = matches!(input, Input::Event(Event::Eof));
+        runtime
+            .process(input)
+            .context("Node step function failed")?;
+        if eof {
+            break;
+        }
+    }
+
+    reader
+        .join()
+        .expect("stdin thread panicked")
+        .context("stdin thread failed")
+}
+
+impl Retry {
+    fn exhausted(&self) -> bool {
+        self.policy
+            .max_attempts
+            .is_some_and(|max_attempts| self.attempts >= max_attempts)
+    }
+}
+
+fn typed_callback<N, Resp, F>(callback: F) -> Callback<N>
+where
+    Resp: DeserializeOwned,
+    F: FnOnce(&mut N, Result<Message<Resp>, Error>, &mut Output<N>) -> anyhow::Result<()> + 'static,
+{
+    // A reply that does not decode is the callback's to deal with, like any
+    // other failed RPC, rather than a reason for the node to stop.
+    let malformed =
+        |err: anyhow::Error| Error::new(ErrorCode::MalformedRequest, format!("{err:#}"));
+    Box::new(move |node, reply, output| {
+        let reply = match reply {
+            Ok(reply) if reply.body.payload["type"] == "error" => {
+                match reply
+                    .decode::<Error>()
+                    .context("RPC error reply can not be deserialized")
+                {
+                    Ok(reply) => Err(reply.body.payload),
+                    Err(err) => Err(malformed(err)),
+                }
+            }
+            Ok(reply) => reply
+                .decode()
+                .context("RPC reply can not be deserialized")
+                .map_err(malformed),
+            Err(err) => Err(err),
+        };
+        callback(node, reply, output)
+    })
+}
+
+fn read_stdin<P, T>(tx: &Sender<Input<P, T>>) -> anyhow::Result<()> {
+    for line in std::io::stdin().lock().lines() {
+        let line = line.context("Maelstrom input from STDIN could not be read")?;
+        let input: Message<Value> = serde_json::from_str(&line)
+            .context("Maelstrom input from STDIN can not be deserialized")?;
+        if tx.send(Input::Wire(input)).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    #[allow(dead_code)]
+    enum Payload {
+        Read,
+        Add { delta: usize },
+    }
+
+    #[test]
+    fn unknown_types_are_told_apart_from_bad_fields() {
+        assert!(is_unknown_type::<Payload>("txn"));
+        assert!(!is_unknown_type::<Payload>("read"));
+        assert!(!is_unknown_type::<Payload>("add"));
+    }
+
+    #[test]
+    fn undecodable_replies_reach_the_callback_as_errors() -> anyhow::Result<()> {
+        let callback = typed_callback(
+            |node: &mut Option<ErrorCode>, reply: Result<Message<Payload>, Error>, _output| {
+                *node = reply.err().map(|err| err.code);
+                Ok(())
+            },
+        );
+        let reply = Message {
+            src: "lin-kv".to_string(),
+            dest: "n0".to_string(),
+            body: Body {
+                payload: serde_json::json!({"type": "add", "delta": "one"}),
+                in_reply_to: Some(0),
+                msg_id: None,
+            },
+        };
+        let mut node = None;
+        let mut output = Output::new("n0".to_string(), Box::new(std::io::sink()));
+        callback(&mut node, Ok(reply), &mut output)?;
+        assert_eq!(node, Some(ErrorCode::MalformedRequest));
+        Ok(())
+    }
+
+    #[test]
+    fn msg_ids_do_not_restart_with_the_node() {
+        let clock = sim::SimClock::new();
+        let boot = |clock: &sim::SimClock| {
+            Output::<()>::new("n0".to_string(), Box::new(std::io::sink()))
+                .with_clock(Rc::new(clock.clone()))
+        };
+        let before = boot(&clock);
+        let sent: Vec<usize> = (0..1_000).map(|_| before.next_msg_id()).collect();
+        clock.set(Duration::from_millis(10));
+        let after = boot(&clock);
+        assert!(after.next_msg_id() > sent[sent.len() - 1]);
+    }
 }
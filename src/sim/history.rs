@@ -0,0 +1,186 @@
+use anyhow::Context;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// Whether a history entry starts an operation or records how it ended, in
+/// the Jepsen sense: `fail` means the operation certainly did not happen,
+/// `info` means it may or may not have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OpType {
+    Invoke,
+    Ok,
+    Fail,
+    Info,
+}
+
+/// Who an entry belongs to: a client process, the nemesis injecting faults
+/// or a node tracing what it did, see [`crate::Output::trace`]. In the
+/// history they are a number, `"nemesis"` and `"node"`, respectively; a
+/// node's entries name it in [`Op::node`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Process {
+    Client(usize),
+    Nemesis,
+    Node,
+}
+
+impl Serialize for Process {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Process::Client(process) => serializer.serialize_u64(*process as u64),
+            Process::Nemesis => serializer.serialize_str("nemesis"),
+            Process::Node => serializer.serialize_str("node"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Process {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Client(usize),
+            Name(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Client(process) => Ok(Process::Client(process)),
+            Raw::Name(name) if name == "nemesis" => Ok(Process::Nemesis),
+            Raw::Name(name) if name == "node" => Ok(Process::Node),
+            Raw::Name(name) => Err(serde::de::Error::custom(format!(
+                "unknown process `{name}`"
+            ))),
+        }
+    }
+}
+
+/// One line of a history.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Op {
+    pub process: Process,
+    #[serde(rename = "type")]
+    pub kind: OpType,
+    /// The operation, e.g. `broadcast` or `read`.
+    pub f: String,
+    pub value: Value,
+    /// The node the client talked to, or that traced the entry; empty for
+    /// the nemesis.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub node: String,
+    /// Nanoseconds since the start of the run.
+    pub time: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Op {
+    pub fn time(&self) -> Duration {
+        Duration::from_nanos(self.time)
+    }
+}
+
+/// An invocation together with the entry that completed it, if any.
+#[derive(Debug, Clone, Copy)]
+pub struct Pair<'a> {
+    pub invoke: &'a Op,
+    pub completion: Option<&'a Op>,
+}
+
+impl Pair<'_> {
+    pub fn is_ok(&self) -> bool {
+        self.completion.is_some_and(|op| op.kind == OpType::Ok)
+    }
+
+    /// Whether the operation may have taken effect: it did not fail outright.
+    pub fn may_have_happened(&self) -> bool {
+        self.completion.is_none_or(|op| op.kind != OpType::Fail)
+    }
+
+    pub fn latency(&self) -> Option<Duration> {
+        self.completion
+            .map(|op| op.time().saturating_sub(self.invoke.time()))
+    }
+}
+
+/// The operations of a run, in the order they happened.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct History {
+    pub ops: Vec<Op>,
+}
+
+impl History {
+    pub fn push(&mut self, op: Op) {
+        self.ops.push(op);
+    }
+
+    /// Reads a history written by [`History::write`]: one JSON op per line.
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("can not open history {}", path.display()))?;
+        let mut ops = Vec::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.context("can not read history")?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            ops.push(
+                serde_json::from_str(&line)
+                    .with_context(|| format!("malformed op on line {}", number + 1))?,
+            );
+        }
+        Ok(History { ops })
+    }
+
+    /// What the nemesis did, in order.
+    pub fn nemesis(&self) -> impl Iterator<Item = &Op> {
+        self.ops.iter().filter(|op| op.process == Process::Nemesis)
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("can not create history {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        for op in &self.ops {
+            serde_json::to_writer(&mut writer, op).context("Can not serialize")?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush().context("can not write history")
+    }
+
+    /// What the nodes traced, in order.
+    pub fn traces(&self) -> impl Iterator<Item = &Op> {
+        self.ops.iter().filter(|op| op.process == Process::Node)
+    }
+
+    /// Matches every client invocation with the next entry of the same
+    /// process.
+    pub fn pairs(&self) -> Vec<Pair<'_>> {
+        let mut open: HashMap<Process, usize> = HashMap::new();
+        let mut pairs = Vec::new();
+        let clients = self
+            .ops
+            .iter()
+            .filter(|op| matches!(op.process, Process::Client(_)));
+        for op in clients {
+            match op.kind {
+                OpType::Invoke => {
+                    open.insert(op.process, pairs.len());
+                    pairs.push(Pair {
+                        invoke: op,
+                        completion: None,
+                    });
+                }
+                _ => {
+                    if let Some(index) = open.remove(&op.process) {
+                        pairs[index].completion = Some(op);
+                    }
+                }
+            }
+        }
+        pairs
+    }
+}
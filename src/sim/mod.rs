@@ -0,0 +1,943 @@
+//! A Maelstrom-compatible network simulator that runs nodes in-process.
+//!
+//! Every binary doubles as its own test harness:
+//!
+//! ```text
+//! cargo run --bin 3 -- simulate --workload broadcast --node-count 5 --seed 7
+//! ```
+//!
+//! boots five copies of the broadcast node, sends them `init`, routes their
+//! messages by `dest` and plays the broadcast workload against them. Time is
+//! simulated and every random choice comes from `--seed`, so a run needs
+//! neither Java nor a network and can be repeated.
+
+pub mod history;
+pub mod nemesis;
+mod node;
+mod services;
+pub mod workload;
+
+use crate::checker;
+use crate::rng::Rng;
+use crate::{Body, Init, InitPayload, Message, Node};
+use anyhow::Context;
+use history::{History, Op, OpType, Process};
+use nemesis::{LatencyDist, Nemesis, NemesisConfig, PartitionKind};
+use node::{InProcess, SimNode};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use services::Services;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::path::PathBuf;
+use std::time::Duration;
+use workload::{Request, Workload, WorkloadKind};
+
+pub use node::SimClock;
+
+/// Options of a simulated run, mirroring Maelstrom's `test` flags.
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    pub workload: WorkloadKind,
+    pub node_count: usize,
+    pub seed: u64,
+    /// How long clients keep issuing operations.
+    pub time_limit: Duration,
+    /// Operations per second, across all clients.
+    pub rate: f64,
+    /// Number of clients issuing operations at the same time.
+    pub concurrency: usize,
+    /// Mean message latency, distributed as `nemesis.latency_dist` says.
+    pub latency: Duration,
+    /// How long the cluster gets to converge before final operations.
+    pub recovery: Duration,
+    /// How long a client waits for a reply before giving up.
+    pub timeout: Duration,
+    /// Where to write the history, as one JSON op per line.
+    pub history: Option<PathBuf>,
+    pub nemesis: NemesisConfig,
+}
+
+impl SimConfig {
+    pub fn new(workload: WorkloadKind) -> Self {
+        SimConfig {
+            workload,
+            node_count: 5,
+            seed: 0,
+            time_limit: Duration::from_secs(10),
+            rate: 100.0,
+            concurrency: 5,
+            latency: Duration::ZERO,
+            recovery: Duration::from_secs(5),
+            timeout: Duration::from_secs(5),
+            history: None,
+            nemesis: NemesisConfig::default(),
+        }
+    }
+
+    /// Parses the arguments following `simulate`:
+    ///
+    /// ```text
+    /// --workload NAME     echo, unique-ids, broadcast, g-counter, kafka or lin-kv
+    /// --node-count N      number of nodes (5)
+    /// --seed N            seed of every random choice (derived from the clock)
+    /// --time-limit SECS   how long clients issue operations (10)
+    /// --rate N            operations per second (100)
+    /// --concurrency N     number of clients (node count)
+    /// --latency MS        mean message latency (0)
+    /// --recovery SECS     quiet period before final operations (5)
+    /// --timeout SECS      client request timeout (5)
+    /// --history PATH      write the history to PATH
+    /// --latency-dist NAME constant, uniform or exponential (exponential)
+    /// --nemesis FAULTS    comma-separated faults to inject now and then:
+    ///                     partition, kill (crash a node and restart it)
+    /// --nemesis-interval SECS
+    ///                     how long partitions, crashes and the healthy
+    ///                     periods in between last (5)
+    /// --partition KINDS   comma-separated partitions to choose from:
+    ///                     halves, isolate, asymmetric (all of them)
+    /// --drop P            probability a message between nodes is lost (0)
+    /// --duplicate P       probability it is delivered twice (0)
+    /// --reorder P         probability it is held back and overtaken (0)
+    /// ```
+    ///
+    /// Message faults only ever affect messages between nodes. All faults
+    /// stop when the clients do, so the final operations see a healthy
+    /// cluster.
+    pub fn from_args(args: impl Iterator<Item = String>) -> anyhow::Result<Self> {
+        let mut flags = HashMap::new();
+        let mut args = args.peekable();
+        while let Some(flag) = args.next() {
+            let name = flag
+                .strip_prefix("--")
+                .with_context(|| format!("expected a flag, got `{flag}`"))?
+                .to_string();
+            let value = args
+                .next()
+                .with_context(|| format!("missing value for `{flag}`"))?;
+            flags.insert(name, value);
+        }
+
+        let workload = flags
+            .remove("workload")
+            .context("`--workload` is required")?;
+        let mut config = SimConfig::new(WorkloadKind::parse(&workload)?);
+        config.seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+        let mut concurrency = None;
+        let mut partition = false;
+        let mut partitions = PartitionKind::ALL.to_vec();
+        for (name, value) in flags {
+            let number = || -> anyhow::Result<f64> {
+                value
+                    .parse()
+                    .with_context(|| format!("`--{name}` expects a number, got `{value}`"))
+            };
+            let probability = || -> anyhow::Result<f64> {
+                let p = number()?;
+                anyhow::ensure!(
+                    (0.0..=1.0).contains(&p),
+                    "`--{name}` expects a probability, got `{value}`"
+                );
+                Ok(p)
+            };
+            match name.as_str() {
+                "node-count" => config.node_count = number()? as usize,
+                "seed" => config.seed = value.parse().context("`--seed` expects an integer")?,
+                "time-limit" => config.time_limit = Duration::from_secs_f64(number()?),
+                "rate" => config.rate = number()?,
+                "concurrency" => concurrency = Some(number()? as usize),
+                "latency" => config.latency = Duration::from_secs_f64(number()? / 1000.0),
+                "recovery" => config.recovery = Duration::from_secs_f64(number()?),
+                "timeout" => config.timeout = Duration::from_secs_f64(number()?),
+                "history" => config.history = Some(PathBuf::from(value)),
+                "latency-dist" => config.nemesis.latency_dist = LatencyDist::parse(&value)?,
+                "nemesis" => {
+                    for fault in value.split(',') {
+                        match fault {
+                            "partition" => partition = true,
+                            "kill" => config.nemesis.kill = true,
+                            _ => anyhow::bail!(
+                                "unknown nemesis `{fault}`, expected partition or kill"
+                            ),
+                        }
+                    }
+                }
+                "nemesis-interval" => config.nemesis.interval = Duration::from_secs_f64(number()?),
+                "partition" => {
+                    partitions = value
+                        .split(',')
+                        .map(PartitionKind::parse)
+                        .collect::<anyhow::Result<_>>()?
+                }
+                "drop" => config.nemesis.drop = probability()?,
+                "duplicate" => config.nemesis.duplicate = probability()?,
+                "reorder" => config.nemesis.reorder = probability()?,
+                _ => anyhow::bail!("unknown flag `--{name}`"),
+            }
+        }
+        config.concurrency = concurrency.unwrap_or(config.node_count);
+        if partition {
+            config.nemesis.partitions = partitions;
+        }
+        anyhow::ensure!(
+            !config.nemesis.interval.is_zero(),
+            "`--nemesis-interval` must be positive"
+        );
+        anyhow::ensure!(config.node_count > 0, "`--node-count` must be positive");
+        anyhow::ensure!(config.concurrency > 0, "`--concurrency` must be positive");
+        anyhow::ensure!(config.rate > 0.0, "`--rate` must be positive");
+        Ok(config)
+    }
+}
+
+/// What a run did, besides its history.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    /// Messages sent from one node to another.
+    pub server_messages: u64,
+    /// Messages between nodes the nemesis dropped, partitions included.
+    pub dropped: u64,
+    /// Messages between nodes the nemesis delivered twice.
+    pub duplicated: u64,
+    /// Simulated time the run took.
+    pub duration: Duration,
+    /// Worker operations that completed ok during the main phase.
+    pub main_ok: u64,
+    /// Simulated time the main phase took.
+    pub main_duration: Duration,
+}
+
+/// Boots `config.node_count` copies of `N`, plays the configured workload
+/// against them, prints a summary and checks the history. The history is
+/// written to `config.history` if set.
+pub fn simulate<S, N, P, T>(init_state: S, config: SimConfig) -> anyhow::Result<()>
+where
+    S: Clone + 'static,
+    N: Node<S, P, T> + 'static,
+    P: DeserializeOwned + 'static,
+    T: Clone + 'static,
+{
+    let (history, report) = simulate_run::<S, N, P, T>(init_state, &config)?;
+    if let Some(path) = &config.history {
+        history.write(path)?;
+    }
+    print_summary(&config, &history, &report);
+    if let Some(verdict) = checker::check(config.workload, &history) {
+        println!("{verdict}");
+        anyhow::ensure!(
+            verdict.valid,
+            "seed {} produced an invalid history",
+            config.seed
+        );
+    }
+    Ok(())
+}
+
+/// Like [`simulate`], but hands back the history instead of printing it.
+pub fn simulate_run<S, N, P, T>(
+    init_state: S,
+    config: &SimConfig,
+) -> anyhow::Result<(History, Report)>
+where
+    S: Clone + 'static,
+    N: Node<S, P, T> + 'static,
+    P: DeserializeOwned + 'static,
+    T: Clone + 'static,
+{
+    let node_ids: Vec<String> = (0..config.node_count).map(|i| format!("n{i}")).collect();
+    let clock = SimClock::new();
+    let boot: Boot = {
+        let node_ids = node_ids.clone();
+        let clock = clock.clone();
+        Box::new(move |i| {
+            let node_id = &node_ids[i];
+            let init = Message {
+                src: "c0".to_string(),
+                dest: node_id.clone(),
+                body: Body {
+                    payload: InitPayload::Init(Init {
+                        node_id: node_id.clone(),
+                        node_ids: node_ids.clone(),
+                    }),
+                    in_reply_to: None,
+                    msg_id: Some(i),
+                },
+            };
+            let mut node = InProcess::<S, N, P, T>::boot(init_state.clone(), init, clock.clone())
+                .with_context(|| format!("{node_id} failed to boot"))?;
+            // The init_ok goes to the harness, which has nothing to do with it.
+            node.drain()?;
+            Ok(Box::new(node) as Box<dyn SimNode>)
+        })
+    };
+
+    let mut simulation = Simulation::new(config.clone(), clock, node_ids, boot)?;
+    simulation.run()?;
+    let report = Report {
+        server_messages: simulation.server_messages,
+        dropped: simulation.dropped,
+        duplicated: simulation.duplicated,
+        duration: simulation.now,
+        main_ok: simulation.main_ok,
+        main_duration: simulation.main.1.saturating_sub(simulation.main.0),
+    };
+    Ok((simulation.history, report))
+}
+
+fn print_summary(config: &SimConfig, history: &History, report: &Report) {
+    let pairs = history.pairs();
+    let count = |kind| {
+        pairs
+            .iter()
+            .filter(|pair| pair.completion.map(|op| op.kind) == Some(kind))
+            .count()
+    };
+    let mut latencies: Vec<Duration> = pairs
+        .iter()
+        .filter(|pair| pair.is_ok())
+        .filter_map(|pair| pair.latency())
+        .collect();
+    latencies.sort();
+    let quantile = |q| checker::quantile(&latencies, q);
+
+    println!(
+        "workload {} on {} nodes, seed {}",
+        config.workload.name(),
+        config.node_count,
+        config.seed
+    );
+    println!(
+        "ops: {} invoked, {} ok, {} fail, {} info",
+        pairs.len(),
+        count(OpType::Ok),
+        count(OpType::Fail),
+        count(OpType::Info)
+    );
+    println!(
+        "server messages: {} ({:.2} per op)",
+        report.server_messages,
+        report.server_messages as f64 / pairs.len().max(1) as f64
+    );
+    // Setup, the final reads and the recovery in between are not load.
+    println!(
+        "throughput: {:.1} ok ops/s over the {:?} main phase",
+        report.main_ok as f64 / report.main_duration.as_secs_f64().max(f64::EPSILON),
+        report.main_duration
+    );
+    println!(
+        "latency: p50 {:?}, p95 {:?}, p99 {:?}, max {:?}",
+        quantile(0.5),
+        quantile(0.95),
+        quantile(0.99),
+        latencies.last().copied().unwrap_or_default()
+    );
+    let partitions = history
+        .nemesis()
+        .filter(|op| op.f == "start-partition")
+        .count();
+    let kills = history.nemesis().filter(|op| op.f == "kill").count();
+    if partitions > 0 || kills > 0 || config.nemesis.tampers() {
+        println!(
+            "nemesis: {partitions} partitions, {kills} kills, {} messages dropped, {} duplicated",
+            report.dropped, report.duplicated
+        );
+    }
+    println!("simulated time: {:?}", report.duration);
+}
+
+/// Boots the node with the given index from scratch, as on a fresh start.
+type Boot = Box<dyn Fn(usize) -> anyhow::Result<Box<dyn SimNode>>>;
+
+#[derive(Debug)]
+enum SimEvent {
+    Deliver(Message<Value>),
+    Invoke { process: usize },
+    ClientTimeout { client: String, msg_id: usize },
+    Nemesis,
+    Kill,
+    EndOfMain,
+    Finish,
+}
+
+/// An event due at `at`; `seq` keeps events due at the same time in the
+/// order they were scheduled.
+#[derive(Debug)]
+struct Scheduled {
+    at: Duration,
+    seq: u64,
+    event: SimEvent,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.at, self.seq).cmp(&(other.at, other.seq))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// Waiting for the replies to the workload's setup requests.
+    Setup,
+    /// Clients issue operations.
+    Main,
+    /// Waiting for outstanding client operations to finish.
+    Draining,
+    /// Giving the cluster time to converge.
+    Recovering,
+    /// Waiting for the replies to the workload's final requests.
+    Final,
+    Done,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    /// Issues operations for as long as the run lasts.
+    Worker,
+    /// Issues a single setup or final request.
+    OneShot,
+}
+
+struct Client {
+    process: usize,
+    node: String,
+    role: Role,
+    next_msg_id: usize,
+    pending: Option<(usize, Request)>,
+}
+
+struct Simulation {
+    config: SimConfig,
+    workload: Box<dyn Workload>,
+    rng: Rng,
+    clock: SimClock,
+    now: Duration,
+    queue: BinaryHeap<Reverse<Scheduled>>,
+    seq: u64,
+    node_ids: Vec<String>,
+    nodes: Vec<Box<dyn SimNode>>,
+    boot: Boot,
+    /// The node that is currently crashed, if any.
+    down: Option<usize>,
+    services: Services,
+    clients: HashMap<String, Client>,
+    next_process: usize,
+    phase: Phase,
+    one_shots_pending: usize,
+    history: History,
+    nemesis: Nemesis,
+    server_messages: u64,
+    dropped: u64,
+    duplicated: u64,
+    /// When the main phase started and ended.
+    main: (Duration, Duration),
+    main_ok: u64,
+}
+
+impl Simulation {
+    fn new(
+        config: SimConfig,
+        clock: SimClock,
+        node_ids: Vec<String>,
+        boot: Boot,
+    ) -> anyhow::Result<Self> {
+        let nodes = (0..node_ids.len())
+            .map(&boot)
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Simulation {
+            workload: config.workload.build(),
+            rng: Rng::new(config.seed),
+            clock,
+            now: Duration::ZERO,
+            queue: BinaryHeap::new(),
+            seq: 0,
+            node_ids,
+            nodes,
+            boot,
+            down: None,
+            services: Services::default(),
+            clients: HashMap::new(),
+            next_process: config.concurrency,
+            phase: Phase::Setup,
+            one_shots_pending: 0,
+            history: History::default(),
+            nemesis: Nemesis::new(config.nemesis.clone()),
+            server_messages: 0,
+            dropped: 0,
+            duplicated: 0,
+            main: (Duration::ZERO, Duration::ZERO),
+            main_ok: 0,
+            config,
+        })
+    }
+
+    fn run(&mut self) -> anyhow::Result<()> {
+        for node in 0..self.nodes.len() {
+            self.record_traces(node);
+        }
+        if self.config.nemesis.tampers() {
+            self.record_nemesis("start-faults", self.config.nemesis.faults());
+        }
+        let setup = self.workload.setup(&self.node_ids);
+        self.issue_one_shots(setup);
+        if self.one_shots_pending == 0 {
+            self.start_main();
+        }
+
+        // Nodes keep their timers running forever, so give up on runs that
+        // fail to finish long after they should have.
+        let give_up = self.config.time_limit + self.config.recovery + self.config.timeout * 4;
+        while self.phase != Phase::Done {
+            let next_event = self.queue.peek().map(|Reverse(scheduled)| scheduled.at);
+            let next_wakeup = self
+                .nodes
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| self.down != Some(i))
+                .filter_map(|(i, node)| node.next_wakeup().map(|at| (at, i)))
+                .min();
+            // Nodes wake up first only when strictly earlier, so a tick never
+            // overtakes a message due at the same instant.
+            let wakeup = next_wakeup.filter(|&(at, _)| next_event.is_none_or(|event| at < event));
+            if let Some((at, i)) = wakeup {
+                self.advance(at);
+                self.nodes[i]
+                    .wake()
+                    .with_context(|| format!("{} crashed", self.node_ids[i]))?;
+                self.flush(i)?;
+            } else if let Some(Reverse(scheduled)) = self.queue.pop() {
+                self.advance(scheduled.at);
+                self.handle(scheduled.event)?;
+            } else {
+                break;
+            }
+            anyhow::ensure!(
+                self.now <= give_up,
+                "simulation did not finish within {give_up:?} of simulated time"
+            );
+        }
+        Ok(())
+    }
+
+    fn advance(&mut self, to: Duration) {
+        self.now = self.now.max(to);
+        self.clock.set(self.now);
+    }
+
+    fn schedule(&mut self, after: Duration, event: SimEvent) {
+        self.seq += 1;
+        self.queue.push(Reverse(Scheduled {
+            at: self.now + after,
+            seq: self.seq,
+            event,
+        }));
+    }
+
+    fn handle(&mut self, event: SimEvent) -> anyhow::Result<()> {
+        match event {
+            SimEvent::Deliver(message) => self.deliver(message),
+            SimEvent::Invoke { process } => {
+                self.invoke(process);
+                Ok(())
+            }
+            SimEvent::ClientTimeout { client, msg_id } => {
+                self.time_out(&client, msg_id);
+                Ok(())
+            }
+            SimEvent::Nemesis => {
+                if self.phase != Phase::Main {
+                    return Ok(());
+                }
+                if self.nemesis.is_partitioned() {
+                    self.nemesis.heal();
+                    self.record_nemesis("stop-partition", Value::Null);
+                } else {
+                    let grudge = self.nemesis.partition(&self.node_ids, &mut self.rng);
+                    self.record_nemesis("start-partition", grudge);
+                }
+                self.schedule(self.config.nemesis.interval, SimEvent::Nemesis);
+                Ok(())
+            }
+            SimEvent::Kill => {
+                if self.phase != Phase::Main {
+                    return Ok(());
+                }
+                if self.down.is_some() {
+                    self.restart()?;
+                } else {
+                    let node = self.rng.below(self.nodes.len() as u64) as usize;
+                    self.down = Some(node);
+                    self.record_nemesis("kill", Value::from(self.node_ids[node].clone()));
+                }
+                self.schedule(self.config.nemesis.interval, SimEvent::Kill);
+                Ok(())
+            }
+            SimEvent::EndOfMain => {
+                if self.down.is_some() {
+                    self.restart()?;
+                }
+                if self.nemesis.is_partitioned() {
+                    self.record_nemesis("stop-partition", Value::Null);
+                }
+                if self.config.nemesis.tampers() {
+                    self.record_nemesis("stop-faults", Value::Null);
+                }
+                self.nemesis.stop();
+                self.phase = Phase::Draining;
+                self.main.1 = self.now;
+                self.maybe_recover();
+                Ok(())
+            }
+            SimEvent::Finish => {
+                let requests = self.workload.finish(&self.node_ids);
+                self.phase = Phase::Final;
+                self.issue_one_shots(requests);
+                if self.one_shots_pending == 0 {
+                    self.phase = Phase::Done;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Puts a message on the wire, where the nemesis gets to tamper with it
+    /// if it travels between two nodes.
+    fn route(&mut self, message: Message<Value>) {
+        if !(self.is_node(&message.src) && self.is_node(&message.dest)) {
+            let latency = self
+                .config
+                .nemesis
+                .latency_dist
+                .sample(self.config.latency, &mut self.rng);
+            self.schedule(latency, SimEvent::Deliver(message));
+            return;
+        }
+
+        self.server_messages += 1;
+        let deliveries = self.nemesis.deliveries(
+            &message.src,
+            &message.dest,
+            self.config.latency,
+            &mut self.rng,
+        );
+        match deliveries.len() {
+            0 => self.dropped += 1,
+            1 => {}
+            _ => self.duplicated += 1,
+        }
+        for latency in deliveries {
+            self.schedule(latency, SimEvent::Deliver(message.clone()));
+        }
+    }
+
+    /// Brings the crashed node back with nothing but what `init` gives it.
+    /// Whatever it had in memory, pending RPCs and timers included, is gone.
+    fn restart(&mut self) -> anyhow::Result<()> {
+        let Some(node) = self.down.take() else {
+            return Ok(());
+        };
+        self.nodes[node] = (self.boot)(node)?;
+        self.record_nemesis("start", Value::from(self.node_ids[node].clone()));
+        self.record_traces(node);
+        Ok(())
+    }
+
+    fn record_nemesis(&mut self, f: &str, value: Value) {
+        self.history.push(Op {
+            process: Process::Nemesis,
+            kind: OpType::Info,
+            f: f.to_string(),
+            value,
+            node: String::new(),
+            time: self.now.as_nanos() as u64,
+            error: None,
+        });
+    }
+
+    fn record_traces(&mut self, node: usize) {
+        for (f, value) in self.nodes[node].traces() {
+            self.history.push(Op {
+                process: Process::Node,
+                kind: OpType::Info,
+                f,
+                value,
+                node: self.node_ids[node].clone(),
+                time: self.now.as_nanos() as u64,
+                error: None,
+            });
+        }
+    }
+
+    fn is_node(&self, id: &str) -> bool {
+        self.node_ids.iter().any(|node| node == id)
+    }
+
+    fn deliver(&mut self, message: Message<Value>) -> anyhow::Result<()> {
+        if let Some(i) = self.node_ids.iter().position(|id| *id == message.dest) {
+            // A crashed node loses whatever reaches it.
+            if self.down == Some(i) {
+                return Ok(());
+            }
+            self.nodes[i]
+                .deliver(message)
+                .with_context(|| format!("{} crashed", self.node_ids[i]))?;
+            self.flush(i)
+        } else if Services::is_service(&message.dest) {
+            let reply = self.services.handle(&message);
+            self.route(reply);
+            Ok(())
+        } else {
+            self.complete(message);
+            Ok(())
+        }
+    }
+
+    fn flush(&mut self, node: usize) -> anyhow::Result<()> {
+        self.record_traces(node);
+        let mut messages = self.nodes[node]
+            .drain()
+            .with_context(|| format!("{} crashed", self.node_ids[node]))?;
+        // Nodes often fan out by iterating a `HashMap`, whose order changes
+        // from one process to the next. Sorting keeps the latencies drawn for
+        // a batch, and so the whole run, a function of the seed alone.
+        messages.sort_by(|a, b| a.dest.cmp(&b.dest));
+        for message in messages {
+            self.route(message);
+        }
+        Ok(())
+    }
+
+    fn client_id(process: usize) -> String {
+        format!("c{}", process + 1)
+    }
+
+    fn start_main(&mut self) {
+        self.phase = Phase::Main;
+        self.main.0 = self.now;
+        for process in 0..self.config.concurrency {
+            let node = self.node_ids[process % self.node_ids.len()].clone();
+            self.clients.insert(
+                Self::client_id(process),
+                Client {
+                    process,
+                    node,
+                    role: Role::Worker,
+                    next_msg_id: 0,
+                    pending: None,
+                },
+            );
+            let delay = self.think_time();
+            self.schedule(delay, SimEvent::Invoke { process });
+        }
+        if !self.config.nemesis.partitions.is_empty() {
+            self.schedule(self.config.nemesis.interval, SimEvent::Nemesis);
+        }
+        if self.config.nemesis.kill {
+            self.schedule(self.config.nemesis.interval, SimEvent::Kill);
+        }
+        self.schedule(self.config.time_limit, SimEvent::EndOfMain);
+    }
+
+    /// The pause between two operations of the same client that yields the
+    /// configured overall rate.
+    fn think_time(&mut self) -> Duration {
+        let mean = Duration::from_secs_f64(self.config.concurrency as f64 / self.config.rate);
+        self.rng.exponential(mean)
+    }
+
+    fn issue_one_shots(&mut self, requests: Vec<(String, Request)>) {
+        for (node, request) in requests {
+            let process = self.next_process;
+            self.next_process += 1;
+            self.clients.insert(
+                Self::client_id(process),
+                Client {
+                    process,
+                    node,
+                    role: Role::OneShot,
+                    next_msg_id: 0,
+                    pending: None,
+                },
+            );
+            self.one_shots_pending += 1;
+            self.send(process, request);
+        }
+    }
+
+    fn invoke(&mut self, process: usize) {
+        if self.phase != Phase::Main {
+            return;
+        }
+        let request = self.workload.invoke(process, &mut self.rng);
+        self.send(process, request);
+    }
+
+    fn send(&mut self, process: usize, request: Request) {
+        let client_id = Self::client_id(process);
+        let client = self
+            .clients
+            .get_mut(&client_id)
+            .expect("processes always have a client");
+        let msg_id = client.next_msg_id;
+        client.next_msg_id += 1;
+        let node = client.node.clone();
+        self.history.push(Op {
+            process: Process::Client(process),
+            kind: OpType::Invoke,
+            f: request.f.to_string(),
+            value: request.value.clone(),
+            node: node.clone(),
+            time: self.now.as_nanos() as u64,
+            error: None,
+        });
+        let message = Message {
+            src: client_id.clone(),
+            dest: node,
+            body: Body {
+                payload: request.body.clone(),
+                in_reply_to: None,
+                msg_id: Some(msg_id),
+            },
+        };
+        client.pending = Some((msg_id, request));
+        self.route(message);
+        self.schedule(
+            self.config.timeout,
+            SimEvent::ClientTimeout {
+                client: client_id,
+                msg_id,
+            },
+        );
+    }
+
+    /// Records the reply to a client's outstanding request.
+    fn complete(&mut self, reply: Message<Value>) {
+        let Some(client) = self.clients.get_mut(&reply.dest) else {
+            return;
+        };
+        let Some((_, request)) = client
+            .pending
+            .take_if(|(msg_id, _)| reply.body.in_reply_to == Some(*msg_id))
+        else {
+            return;
+        };
+        let process = client.process;
+        let node = client.node.clone();
+        let worker = client.role == Role::Worker;
+        let payload = reply.body.payload;
+        let (kind, value, error) = if payload["type"] == "error" {
+            let error: Result<crate::Error, _> = serde_json::from_value(payload.clone());
+            let definite = error.as_ref().is_ok_and(|error| error.code.is_definite());
+            (
+                if definite { OpType::Fail } else { OpType::Info },
+                request.value.clone(),
+                Some(payload.to_string()),
+            )
+        } else {
+            (
+                OpType::Ok,
+                self.workload.complete(process, &request, &payload),
+                None,
+            )
+        };
+        if kind == OpType::Ok && worker && self.phase == Phase::Main {
+            self.main_ok += 1;
+        }
+        self.history.push(Op {
+            process: Process::Client(process),
+            kind,
+            f: request.f.to_string(),
+            value,
+            node,
+            time: self.now.as_nanos() as u64,
+            error,
+        });
+        self.finished(&reply.dest);
+    }
+
+    fn time_out(&mut self, client_id: &str, msg_id: usize) {
+        let Some(client) = self.clients.get_mut(client_id) else {
+            return;
+        };
+        let Some((_, request)) = client.pending.take_if(|(pending, _)| *pending == msg_id) else {
+            return;
+        };
+        self.history.push(Op {
+            process: Process::Client(client.process),
+            kind: OpType::Info,
+            f: request.f.to_string(),
+            value: request.value,
+            node: client.node.clone(),
+            time: self.now.as_nanos() as u64,
+            error: Some("timeout".to_string()),
+        });
+
+        // As in Jepsen, a process whose operation is in limbo is replaced by
+        // a fresh one, so every process has at most one open operation.
+        if client.role == Role::Worker {
+            let mut client = self.clients.remove(client_id).expect("looked up above");
+            client.process = self.next_process;
+            self.next_process += 1;
+            let client_id = Self::client_id(client.process);
+            self.clients.insert(client_id.clone(), client);
+            self.finished(&client_id);
+        } else {
+            self.finished(client_id);
+        }
+    }
+
+    /// Moves on after a client's operation ended one way or another.
+    fn finished(&mut self, client_id: &str) {
+        let client = &self.clients[client_id];
+        match client.role {
+            Role::Worker => {
+                if self.phase == Phase::Main {
+                    let process = client.process;
+                    let delay = self.think_time();
+                    self.schedule(delay, SimEvent::Invoke { process });
+                } else {
+                    self.maybe_recover();
+                }
+            }
+            Role::OneShot => {
+                self.one_shots_pending -= 1;
+                if self.one_shots_pending > 0 {
+                    return;
+                }
+                match self.phase {
+                    Phase::Setup => self.start_main(),
+                    Phase::Final => self.phase = Phase::Done,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Once the last client operation is done, waits for the cluster to
+    /// converge before the final requests.
+    fn maybe_recover(&mut self) {
+        let busy = self
+            .clients
+            .values()
+            .any(|client| client.role == Role::Worker && client.pending.is_some());
+        if self.phase == Phase::Draining && !busy {
+            self.phase = Phase::Recovering;
+            self.schedule(self.config.recovery, SimEvent::Finish);
+        }
+    }
+}
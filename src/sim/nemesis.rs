@@ -0,0 +1,224 @@
+use crate::rng::Rng;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+/// How message latencies are distributed around `--latency`, as in
+/// Maelstrom's `--latency-dist`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LatencyDist {
+    Constant,
+    Uniform,
+    #[default]
+    Exponential,
+}
+
+impl LatencyDist {
+    pub fn parse(name: &str) -> anyhow::Result<Self> {
+        Ok(match name {
+            "constant" => LatencyDist::Constant,
+            "uniform" => LatencyDist::Uniform,
+            "exponential" => LatencyDist::Exponential,
+            _ => anyhow::bail!(
+                "unknown latency distribution `{name}`, expected constant, uniform or exponential"
+            ),
+        })
+    }
+
+    /// A latency whose mean is `mean`.
+    pub fn sample(self, mean: Duration, rng: &mut Rng) -> Duration {
+        match self {
+            LatencyDist::Constant => mean,
+            LatencyDist::Uniform => mean.mul_f64(2.0 * rng.unit()),
+            LatencyDist::Exponential => rng.exponential(mean),
+        }
+    }
+}
+
+/// The ways the nemesis can cut the cluster in two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionKind {
+    /// A random majority and minority that can not reach each other.
+    Halves,
+    /// A single node cut off from everyone else.
+    Isolate,
+    /// A random majority and minority where only the minority's messages
+    /// get lost: the majority can still talk to it.
+    Asymmetric,
+}
+
+impl PartitionKind {
+    pub const ALL: [PartitionKind; 3] = [
+        PartitionKind::Halves,
+        PartitionKind::Isolate,
+        PartitionKind::Asymmetric,
+    ];
+
+    pub fn parse(name: &str) -> anyhow::Result<Self> {
+        Ok(match name {
+            "halves" => PartitionKind::Halves,
+            "isolate" => PartitionKind::Isolate,
+            "asymmetric" => PartitionKind::Asymmetric,
+            _ => {
+                anyhow::bail!("unknown partition `{name}`, expected halves, isolate or asymmetric")
+            }
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PartitionKind::Halves => "halves",
+            PartitionKind::Isolate => "isolate",
+            PartitionKind::Asymmetric => "asymmetric",
+        }
+    }
+}
+
+/// The faults injected into messages between nodes. Messages from and to
+/// clients and services are never tampered with.
+#[derive(Debug, Clone)]
+pub struct NemesisConfig {
+    /// The partitions to choose from; empty disables partitions.
+    pub partitions: Vec<PartitionKind>,
+    /// How long partitions last, and how long the network stays healed in
+    /// between.
+    pub interval: Duration,
+    /// Whether a node is crashed now and then, to come back with its
+    /// memory wiped one interval later.
+    pub kill: bool,
+    /// Probability that a message is lost.
+    pub drop: f64,
+    /// Probability that a message is delivered twice.
+    pub duplicate: f64,
+    /// Probability that a message is held back long enough for later ones to
+    /// overtake it.
+    pub reorder: f64,
+    pub latency_dist: LatencyDist,
+}
+
+impl Default for NemesisConfig {
+    fn default() -> Self {
+        NemesisConfig {
+            partitions: Vec::new(),
+            interval: Duration::from_secs(5),
+            kill: false,
+            drop: 0.0,
+            duplicate: 0.0,
+            reorder: 0.0,
+            latency_dist: LatencyDist::default(),
+        }
+    }
+}
+
+impl NemesisConfig {
+    /// Whether any message between nodes may be lost, duplicated or
+    /// reordered outside of what the latency distribution does anyway.
+    pub fn tampers(&self) -> bool {
+        self.drop > 0.0 || self.duplicate > 0.0 || self.reorder > 0.0
+    }
+
+    /// The message faults as recorded in the history.
+    pub fn faults(&self) -> Value {
+        json!({"drop": self.drop, "duplicate": self.duplicate, "reorder": self.reorder})
+    }
+}
+
+/// Decides the fate of every message between nodes.
+#[derive(Debug)]
+pub(crate) struct Nemesis {
+    config: NemesisConfig,
+    /// For every node, the nodes whose messages it currently does not get.
+    grudge: BTreeMap<String, BTreeSet<String>>,
+    /// Whether the message faults are in effect.
+    tampering: bool,
+}
+
+impl Nemesis {
+    pub(crate) fn new(config: NemesisConfig) -> Self {
+        Nemesis {
+            tampering: config.tampers(),
+            config,
+            grudge: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn is_partitioned(&self) -> bool {
+        !self.grudge.is_empty()
+    }
+
+    /// Splits the cluster and returns the grudge as recorded in the history:
+    /// every node mapped to the nodes it no longer hears from.
+    pub(crate) fn partition(&mut self, nodes: &[String], rng: &mut Rng) -> Value {
+        let kind = *rng.pick(&self.config.partitions);
+        let mut shuffled = nodes.to_vec();
+        rng.shuffle(&mut shuffled);
+        let minority_size = match kind {
+            PartitionKind::Isolate => 1,
+            PartitionKind::Halves | PartitionKind::Asymmetric => (nodes.len() / 2).max(1),
+        };
+        let (minority, majority) = shuffled.split_at(minority_size.min(nodes.len()));
+
+        self.grudge.clear();
+        for node in majority {
+            self.grudge
+                .entry(node.clone())
+                .or_default()
+                .extend(minority.iter().cloned());
+        }
+        if kind != PartitionKind::Asymmetric {
+            for node in minority {
+                self.grudge
+                    .entry(node.clone())
+                    .or_default()
+                    .extend(majority.iter().cloned());
+            }
+        }
+        self.grudge.retain(|_, srcs| !srcs.is_empty());
+        json!({"kind": kind.name(), "grudge": self.grudge})
+    }
+
+    pub(crate) fn heal(&mut self) {
+        self.grudge.clear();
+    }
+
+    /// Turns partitions and message faults off for good, so the cluster can
+    /// recover.
+    pub(crate) fn stop(&mut self) {
+        self.heal();
+        self.tampering = false;
+    }
+
+    /// The delays after which the copies of a message from `src` to `dest`
+    /// arrive; empty if it is lost.
+    pub(crate) fn deliveries(
+        &self,
+        src: &str,
+        dest: &str,
+        latency: Duration,
+        rng: &mut Rng,
+    ) -> Vec<Duration> {
+        if self.grudge.get(dest).is_some_and(|srcs| srcs.contains(src)) {
+            return Vec::new();
+        }
+        if !self.tampering {
+            return vec![self.config.latency_dist.sample(latency, rng)];
+        }
+        if rng.chance(self.config.drop) {
+            return Vec::new();
+        }
+        let copies = if rng.chance(self.config.duplicate) {
+            2
+        } else {
+            1
+        };
+        (0..copies)
+            .map(|_| {
+                let mut delay = self.config.latency_dist.sample(latency, rng);
+                if rng.chance(self.config.reorder) {
+                    delay += rng.exponential((latency * 10).max(Duration::from_millis(10)));
+                }
+                delay
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,234 @@
+use crate::{Clock, InitPayload, Message, Node, Runtime};
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::cell::{Cell, RefCell};
+use std::io::Write;
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// The simulator's virtual clock, shared by every node of a run. It only
+/// moves when the simulator advances it. Wall-clock time starts at
+/// [`SimClock::START`] so that it does not vary from one run to the next.
+#[derive(Clone)]
+pub struct SimClock {
+    epoch: Instant,
+    elapsed: Rc<Cell<Duration>>,
+}
+
+impl SimClock {
+    /// 2024-01-01T00:00:00Z.
+    const START: Duration = Duration::from_secs(1_704_067_200);
+
+    pub fn new() -> Self {
+        SimClock {
+            epoch: Instant::now(),
+            elapsed: Rc::new(Cell::new(Duration::ZERO)),
+        }
+    }
+
+    /// Moves the clock to `elapsed` past the start of the run.
+    pub fn set(&self, elapsed: Duration) {
+        self.elapsed.set(elapsed);
+    }
+
+    /// How far into the run `instant` is.
+    fn since_start(&self, instant: Instant) -> Duration {
+        instant.saturating_duration_since(self.epoch)
+    }
+}
+
+impl Default for SimClock {
+    fn default() -> Self {
+        SimClock::new()
+    }
+}
+
+impl Clock for SimClock {
+    fn now(&self) -> Instant {
+        self.epoch + self.elapsed.get()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Self::START + self.elapsed.get()
+    }
+}
+
+/// A node as the simulator sees it, with its types erased.
+pub(crate) trait SimNode {
+    fn deliver(&mut self, message: Message<Value>) -> anyhow::Result<()>;
+
+    /// When the node next needs to run a timer or look after an RPC, in
+    /// simulated time.
+    fn next_wakeup(&self) -> Option<Duration>;
+
+    /// Fires every timer and RPC deadline that is due.
+    fn wake(&mut self) -> anyhow::Result<()>;
+
+    /// Takes the messages the node sent since the last call.
+    fn drain(&mut self) -> anyhow::Result<Vec<Message<Value>>>;
+
+    /// Takes what the node traced since the last call, see
+    /// [`crate::Output::trace`].
+    fn traces(&mut self) -> Vec<(String, Value)>;
+}
+
+/// Collects what a node writes so the simulator can route it.
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs a [`Node`] inside the simulator's process.
+pub(crate) struct InProcess<S, N, P, T> {
+    runtime: Runtime<S, N, P, T>,
+    buffer: SharedBuffer,
+    clock: SimClock,
+}
+
+impl<S, N, P, T> InProcess<S, N, P, T>
+where
+    N: Node<S, P, T>,
+    P: DeserializeOwned,
+    T: Clone,
+{
+    pub(crate) fn boot(
+        init_state: S,
+        init: Message<InitPayload>,
+        clock: SimClock,
+    ) -> anyhow::Result<Self> {
+        let buffer = SharedBuffer::default();
+        let runtime = Runtime::boot(
+            init_state,
+            init,
+            Box::new(buffer.clone()),
+            Rc::new(clock.clone()),
+            true,
+        )?;
+        Ok(InProcess {
+            runtime,
+            buffer,
+            clock,
+        })
+    }
+}
+
+impl<S, N, P, T> SimNode for InProcess<S, N, P, T>
+where
+    N: Node<S, P, T>,
+    P: DeserializeOwned,
+    T: Clone,
+{
+    fn deliver(&mut self, message: Message<Value>) -> anyhow::Result<()> {
+        self.runtime.deliver(message)?;
+        self.runtime.drain_injected()
+    }
+
+    fn next_wakeup(&self) -> Option<Duration> {
+        self.runtime
+            .next_wakeup()
+            .map(|wakeup| self.clock.since_start(wakeup))
+    }
+
+    fn wake(&mut self) -> anyhow::Result<()> {
+        self.runtime.wake()?;
+        self.runtime.drain_injected()
+    }
+
+    fn drain(&mut self) -> anyhow::Result<Vec<Message<Value>>> {
+        let bytes = std::mem::take(&mut *self.buffer.0.borrow_mut());
+        serde_json::Deserializer::from_slice(&bytes)
+            .into_iter()
+            .map(|message| message.context("node wrote a malformed message"))
+            .collect()
+    }
+
+    fn traces(&mut self) -> Vec<(String, Value)> {
+        self.runtime.take_traces()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hlc::{HlcTimestamp, HLC_FIELD};
+    use crate::{Body, Event, Init, Output, Scheduler};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Payload {
+        Echo,
+        EchoOk,
+    }
+
+    struct Echo;
+
+    impl Node<(), Payload> for Echo {
+        fn from_init(
+            _state: (),
+            _init: Init,
+            _scheduler: &mut Scheduler<Payload, ()>,
+        ) -> anyhow::Result<Self> {
+            Ok(Echo)
+        }
+
+        fn step(&mut self, input: Event<Payload>, output: &mut Output<Self>) -> anyhow::Result<()> {
+            match input {
+                Event::Message(input) => output.send(&input.reply(Payload::EchoOk, output)),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    fn message<P>(src: &str, dest: &str, payload: P) -> Message<P> {
+        Message {
+            src: src.to_string(),
+            dest: dest.to_string(),
+            body: Body {
+                payload,
+                in_reply_to: None,
+                msg_id: Some(0),
+            },
+        }
+    }
+
+    #[test]
+    fn replies_are_stamped_after_their_requests() -> anyhow::Result<()> {
+        let clock = SimClock::new();
+        let init = InitPayload::Init(Init {
+            node_id: "n1".to_string(),
+            node_ids: vec!["n0".to_string(), "n1".to_string()],
+        });
+        let mut node =
+            InProcess::<(), Echo, Payload, ()>::boot((), message("c0", "n1", init), clock.clone())?;
+        node.drain()?;
+
+        let now = clock.system_time().duration_since(SystemTime::UNIX_EPOCH)?;
+        let now = now.as_millis() as u64;
+        // One peer is far ahead of the simulated clock, one behind it, and one
+        // in the same millisecond.
+        for wall in [now + 1_000, now - 1_000, now] {
+            let request = HlcTimestamp { wall, logical: 7 };
+            let mut payload = serde_json::json!({"type": "echo"});
+            payload[HLC_FIELD] = serde_json::to_value(request)?;
+            node.deliver(message("n0", "n1", payload))?;
+
+            let replies = node.drain()?;
+            assert_eq!(replies.len(), 1);
+            let reply: HlcTimestamp =
+                serde_json::from_value(replies[0].body.payload[HLC_FIELD].clone())?;
+            assert!(reply > request, "{reply} is not after {request}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+use crate::kv::Service;
+use crate::tso::LIN_TSO;
+use crate::{Body, Error, ErrorCode, Message};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// In-process stand-ins for Maelstrom's built-in services. Every key/value
+/// service is backed by a single map with linearizable semantics, which is a
+/// valid (if unusually well-behaved) implementation of all three.
+#[derive(Debug, Default)]
+pub(crate) struct Services {
+    stores: HashMap<&'static str, HashMap<String, Value>>,
+    last_ts: u64,
+}
+
+impl Services {
+    pub(crate) fn is_service(dest: &str) -> bool {
+        dest == LIN_TSO
+            || [Service::SeqKv, Service::LinKv, Service::LwwKv]
+                .iter()
+                .any(|service| service.node_id() == dest)
+    }
+
+    /// Handles a request addressed to one of the services and builds its
+    /// reply.
+    pub(crate) fn handle(&mut self, request: &Message<Value>) -> Message<Value> {
+        let payload = match self.apply(&request.dest, &request.body.payload) {
+            Ok(payload) => payload,
+            Err(error) => serde_json::to_value(error).expect("errors always serialize"),
+        };
+        Message {
+            src: request.dest.clone(),
+            dest: request.src.clone(),
+            body: Body {
+                payload,
+                in_reply_to: request.body.msg_id,
+                msg_id: None,
+            },
+        }
+    }
+
+    fn apply(&mut self, service: &str, body: &Value) -> Result<Value, Error> {
+        let kind = body["type"].as_str().unwrap_or_default();
+        if service == LIN_TSO {
+            return match kind {
+                "ts" => {
+                    self.last_ts += 1;
+                    Ok(json!({"type": "ts_ok", "ts": self.last_ts}))
+                }
+                _ => Err(Error::not_supported(format!(
+                    "{LIN_TSO} does not support {kind}"
+                ))),
+            };
+        }
+
+        let store = self
+            .stores
+            .entry(
+                [Service::SeqKv, Service::LinKv, Service::LwwKv]
+                    .into_iter()
+                    .map(Service::node_id)
+                    .find(|&id| id == service)
+                    .expect("only called for known services"),
+            )
+            .or_default();
+        let key = body["key"].to_string();
+        match kind {
+            "read" => match store.get(&key) {
+                Some(value) => Ok(json!({"type": "read_ok", "value": value})),
+                None => Err(key_does_not_exist(&key)),
+            },
+            "write" => {
+                store.insert(key, body["value"].clone());
+                Ok(json!({"type": "write_ok"}))
+            }
+            "cas" => {
+                let create = body["create_if_not_exists"].as_bool().unwrap_or(false);
+                match store.get(&key) {
+                    Some(current) if *current != body["from"] => Err(Error::new(
+                        ErrorCode::PreconditionFailed,
+                        format!("expected {}, but had {current}", body["from"]),
+                    )),
+                    None if !create => Err(key_does_not_exist(&key)),
+                    _ => {
+                        store.insert(key, body["to"].clone());
+                        Ok(json!({"type": "cas_ok"}))
+                    }
+                }
+            }
+            _ => Err(Error::not_supported(format!(
+                "{service} does not support {kind}"
+            ))),
+        }
+    }
+}
+
+fn key_does_not_exist(key: &str) -> Error {
+    Error::new(
+        ErrorCode::KeyDoesNotExist,
+        format!("key {key} does not exist"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::node::{InProcess, SimClock, SimNode};
+    use crate::tso::TsoClient;
+    use crate::{Event, Init, InitPayload, Node, Output, Scheduler};
+    use serde::{Deserialize, Serialize};
+    use std::time::Duration;
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Payload {
+        Fetch,
+        FetchOk { ts: u64 },
+    }
+
+    /// Answers every `fetch` with a timestamp from `lin-tso`.
+    struct Stamper;
+
+    impl Node<(), Payload> for Stamper {
+        fn from_init(
+            _state: (),
+            _init: Init,
+            _scheduler: &mut Scheduler<Payload, ()>,
+        ) -> anyhow::Result<Self> {
+            Ok(Stamper)
+        }
+
+        fn step(&mut self, input: Event<Payload>, output: &mut Output<Self>) -> anyhow::Result<()> {
+            let Event::Message(input) = input else {
+                return Ok(());
+            };
+            let Payload::Fetch = input.body.payload else {
+                return Ok(());
+            };
+            TsoClient::new()
+                .with_timeout(TIMEOUT)
+                .ts(output, move |_, ts, output| {
+                    output.send(&input.reply(Payload::FetchOk { ts: ts? }, output))
+                })
+        }
+    }
+
+    fn message<P>(src: &str, dest: &str, payload: P, msg_id: usize) -> Message<P> {
+        Message {
+            src: src.to_string(),
+            dest: dest.to_string(),
+            body: Body {
+                payload,
+                in_reply_to: None,
+                msg_id: Some(msg_id),
+            },
+        }
+    }
+
+    fn boot(clock: &SimClock) -> anyhow::Result<InProcess<(), Stamper, Payload, ()>> {
+        let init = InitPayload::Init(Init {
+            node_id: "n0".to_string(),
+            node_ids: vec!["n0".to_string()],
+        });
+        let mut node = InProcess::boot((), message("c0", "n0", init, 0), clock.clone())?;
+        node.drain()?;
+        Ok(node)
+    }
+
+    /// Sends the node a `fetch` and returns what it asked `lin-tso`.
+    fn fetch(node: &mut impl SimNode, msg_id: usize) -> anyhow::Result<Message<Value>> {
+        let fetch = serde_json::to_value(Payload::Fetch)?;
+        node.deliver(message("c1", "n0", fetch, msg_id))?;
+        let mut sent = node.drain()?;
+        assert_eq!(sent.len(), 1);
+        let request = sent.remove(0);
+        assert_eq!(request.dest, LIN_TSO);
+        Ok(request)
+    }
+
+    /// Hands the node `lin-tso`'s reply to `request` and returns the
+    /// timestamp the node passed on.
+    fn answer(
+        node: &mut impl SimNode,
+        services: &mut Services,
+        request: &Message<Value>,
+    ) -> anyhow::Result<u64> {
+        node.deliver(services.handle(request))?;
+        let mut sent = node.drain()?;
+        assert_eq!(sent.len(), 1);
+        let reply = sent.remove(0).decode::<Payload>()?;
+        match reply.body.payload {
+            Payload::FetchOk { ts } => Ok(ts),
+            Payload::Fetch => anyhow::bail!("expected a fetch_ok"),
+        }
+    }
+
+    #[test]
+    fn timestamps_strictly_increase() -> anyhow::Result<()> {
+        let clock = SimClock::new();
+        let mut node = boot(&clock)?;
+        let mut services = Services::default();
+        let mut stamps = Vec::new();
+        for msg_id in 1..=5 {
+            let request = fetch(&mut node, msg_id)?;
+            stamps.push(answer(&mut node, &mut services, &request)?);
+        }
+        assert!(
+            stamps.windows(2).all(|pair| pair[0] < pair[1]),
+            "{stamps:?}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unanswered_requests_are_resent() -> anyhow::Result<()> {
+        let clock = SimClock::new();
+        let mut node = boot(&clock)?;
+        let mut services = Services::default();
+        let lost = fetch(&mut node, 1)?;
+
+        assert_eq!(node.next_wakeup(), Some(TIMEOUT / 4));
+        clock.set(TIMEOUT / 4);
+        node.wake()?;
+        let resent = node.drain()?;
+        assert_eq!(resent.len(), 1);
+        assert_eq!(resent[0].dest, LIN_TSO);
+        assert_eq!(resent[0].body.payload, lost.body.payload);
+        assert_eq!(answer(&mut node, &mut services, &resent[0])?, 1);
+
+        // The reply to the first request turns up late, after the RPC has
+        // completed, and is dropped.
+        node.deliver(services.handle(&lost))?;
+        assert!(node.drain()?.is_empty());
+        Ok(())
+    }
+}
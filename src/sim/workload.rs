@@ -0,0 +1,351 @@
+use crate::rng::Rng;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+
+/// A request a client sends to a node.
+#[derive(Debug, Clone)]
+pub struct Request {
+    /// The name of the operation in the history.
+    pub f: &'static str,
+    /// The message body, including its `type`.
+    pub body: Value,
+    /// What the history records for the invocation.
+    pub value: Value,
+}
+
+impl Request {
+    fn new(f: &'static str, body: Value, value: Value) -> Self {
+        Request { f, body, value }
+    }
+}
+
+/// Generates the client side of one of the challenges.
+pub trait Workload {
+    /// Requests sent to the nodes before any client starts, e.g. `topology`.
+    fn setup(&mut self, _nodes: &[String]) -> Vec<(String, Request)> {
+        Vec::new()
+    }
+
+    /// The next operation of `process`.
+    fn invoke(&mut self, process: usize, rng: &mut Rng) -> Request;
+
+    /// Turns a successful reply into the value the history records.
+    fn complete(&mut self, process: usize, request: &Request, reply: &Value) -> Value;
+
+    /// Requests sent to every node once the cluster has had time to recover
+    /// after the clients stopped, e.g. final reads.
+    fn finish(&mut self, _nodes: &[String]) -> Vec<(String, Request)> {
+        Vec::new()
+    }
+}
+
+/// The workloads the simulator can play.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkloadKind {
+    Echo,
+    UniqueIds,
+    Broadcast,
+    GCounter,
+    Kafka,
+    LinKv,
+}
+
+impl WorkloadKind {
+    pub fn parse(name: &str) -> anyhow::Result<Self> {
+        Ok(match name {
+            "echo" => WorkloadKind::Echo,
+            "unique-ids" => WorkloadKind::UniqueIds,
+            "broadcast" => WorkloadKind::Broadcast,
+            "g-counter" => WorkloadKind::GCounter,
+            "kafka" => WorkloadKind::Kafka,
+            "lin-kv" => WorkloadKind::LinKv,
+            _ => anyhow::bail!(
+                "unknown workload `{name}`, expected one of echo, unique-ids, broadcast, g-counter, kafka, lin-kv"
+            ),
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WorkloadKind::Echo => "echo",
+            WorkloadKind::UniqueIds => "unique-ids",
+            WorkloadKind::Broadcast => "broadcast",
+            WorkloadKind::GCounter => "g-counter",
+            WorkloadKind::Kafka => "kafka",
+            WorkloadKind::LinKv => "lin-kv",
+        }
+    }
+
+    pub fn build(self) -> Box<dyn Workload> {
+        match self {
+            WorkloadKind::Echo => Box::new(Echo::default()),
+            WorkloadKind::UniqueIds => Box::new(UniqueIds),
+            WorkloadKind::Broadcast => Box::new(Broadcast::default()),
+            WorkloadKind::GCounter => Box::new(GCounter),
+            WorkloadKind::Kafka => Box::new(Kafka::default()),
+            WorkloadKind::LinKv => Box::new(LinKv),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Echo {
+    next: usize,
+}
+
+impl Workload for Echo {
+    fn invoke(&mut self, _process: usize, _rng: &mut Rng) -> Request {
+        self.next += 1;
+        let echo = format!("Please echo {}", self.next);
+        Request::new("echo", json!({"type": "echo", "echo": echo}), json!(echo))
+    }
+
+    fn complete(&mut self, _process: usize, _request: &Request, reply: &Value) -> Value {
+        reply["echo"].clone()
+    }
+}
+
+struct UniqueIds;
+
+impl Workload for UniqueIds {
+    fn invoke(&mut self, _process: usize, _rng: &mut Rng) -> Request {
+        Request::new("generate", json!({"type": "generate"}), Value::Null)
+    }
+
+    fn complete(&mut self, _process: usize, _request: &Request, reply: &Value) -> Value {
+        reply["id"].clone()
+    }
+}
+
+#[derive(Default)]
+struct Broadcast {
+    next: u64,
+}
+
+impl Workload for Broadcast {
+    fn setup(&mut self, nodes: &[String]) -> Vec<(String, Request)> {
+        let topology = grid_topology(nodes);
+        nodes
+            .iter()
+            .map(|node| {
+                (
+                    node.clone(),
+                    Request::new(
+                        "topology",
+                        json!({"type": "topology", "topology": topology}),
+                        Value::Null,
+                    ),
+                )
+            })
+            .collect()
+    }
+
+    fn invoke(&mut self, _process: usize, rng: &mut Rng) -> Request {
+        if rng.chance(0.5) {
+            let message = self.next;
+            self.next += 1;
+            Request::new(
+                "broadcast",
+                json!({"type": "broadcast", "message": message}),
+                json!(message),
+            )
+        } else {
+            read()
+        }
+    }
+
+    fn complete(&mut self, _process: usize, request: &Request, reply: &Value) -> Value {
+        match request.f {
+            "read" => reply["messages"].clone(),
+            _ => request.value.clone(),
+        }
+    }
+
+    fn finish(&mut self, nodes: &[String]) -> Vec<(String, Request)> {
+        nodes.iter().map(|node| (node.clone(), read())).collect()
+    }
+}
+
+/// Maelstrom's default topology: the nodes laid out row by row on a square
+/// grid, each connected to the nodes above, below, left and right of it.
+pub fn grid_topology(nodes: &[String]) -> BTreeMap<String, Vec<String>> {
+    let width = (nodes.len() as f64).sqrt().ceil().max(1.0) as usize;
+    nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| {
+            let mut neighbors = Vec::new();
+            if i >= width {
+                neighbors.push(nodes[i - width].clone());
+            }
+            if i + width < nodes.len() {
+                neighbors.push(nodes[i + width].clone());
+            }
+            if i % width > 0 {
+                neighbors.push(nodes[i - 1].clone());
+            }
+            if i % width + 1 < width && i + 1 < nodes.len() {
+                neighbors.push(nodes[i + 1].clone());
+            }
+            (node.clone(), neighbors)
+        })
+        .collect()
+}
+
+struct GCounter;
+
+impl Workload for GCounter {
+    fn invoke(&mut self, _process: usize, rng: &mut Rng) -> Request {
+        if rng.chance(0.5) {
+            let delta = rng.below(5);
+            Request::new("add", json!({"type": "add", "delta": delta}), json!(delta))
+        } else {
+            read()
+        }
+    }
+
+    fn complete(&mut self, _process: usize, request: &Request, reply: &Value) -> Value {
+        match request.f {
+            "read" => reply["value"].clone(),
+            _ => request.value.clone(),
+        }
+    }
+
+    fn finish(&mut self, nodes: &[String]) -> Vec<(String, Request)> {
+        nodes.iter().map(|node| (node.clone(), read())).collect()
+    }
+}
+
+fn read() -> Request {
+    Request::new("read", json!({"type": "read"}), Value::Null)
+}
+
+const KAFKA_KEYS: usize = 4;
+
+#[derive(Default)]
+struct Kafka {
+    next: u64,
+    /// Per process, the offset each key is polled from next.
+    positions: HashMap<usize, BTreeMap<String, u64>>,
+}
+
+impl Workload for Kafka {
+    fn invoke(&mut self, process: usize, rng: &mut Rng) -> Request {
+        let positions = self.positions.entry(process).or_default();
+        let keys: Vec<String> = (0..KAFKA_KEYS).map(|key| format!("k{key}")).collect();
+        match rng.below(10) {
+            0..=4 => {
+                let key = rng.pick(&keys).clone();
+                let msg = self.next;
+                self.next += 1;
+                Request::new(
+                    "send",
+                    json!({"type": "send", "key": key, "msg": msg}),
+                    json!([key, msg]),
+                )
+            }
+            5..=7 => {
+                let offsets: BTreeMap<&String, u64> = keys
+                    .iter()
+                    .map(|key| (key, positions.get(key).copied().unwrap_or(0)))
+                    .collect();
+                Request::new(
+                    "poll",
+                    json!({"type": "poll", "offsets": offsets}),
+                    json!(offsets),
+                )
+            }
+            8 => {
+                // Commit everything this process has consumed so far.
+                let offsets: BTreeMap<&String, u64> = positions
+                    .iter()
+                    .filter(|(_, &position)| position > 0)
+                    .map(|(key, &position)| (key, position - 1))
+                    .collect();
+                Request::new(
+                    "commit_offsets",
+                    json!({"type": "commit_offsets", "offsets": offsets}),
+                    json!(offsets),
+                )
+            }
+            _ => Request::new(
+                "list_committed_offsets",
+                json!({"type": "list_committed_offsets", "keys": keys}),
+                json!(keys),
+            ),
+        }
+    }
+
+    fn complete(&mut self, process: usize, request: &Request, reply: &Value) -> Value {
+        match request.f {
+            "send" => {
+                let mut value = request.value.clone();
+                if let Some(value) = value.as_array_mut() {
+                    value.push(reply["offset"].clone());
+                }
+                value
+            }
+            "poll" => {
+                let positions = self.positions.entry(process).or_default();
+                if let Some(msgs) = reply["msgs"].as_object() {
+                    for (key, msgs) in msgs {
+                        let last = msgs
+                            .as_array()
+                            .and_then(|msgs| msgs.last())
+                            .and_then(|msg| msg[0].as_u64());
+                        if let Some(last) = last {
+                            let position = positions.entry(key.clone()).or_default();
+                            *position = (*position).max(last + 1);
+                        }
+                    }
+                }
+                reply["msgs"].clone()
+            }
+            "list_committed_offsets" => reply["offsets"].clone(),
+            _ => request.value.clone(),
+        }
+    }
+}
+
+const LIN_KV_KEYS: u64 = 4;
+const LIN_KV_VALUES: u64 = 5;
+
+/// Reads, writes and compare-and-sets on a handful of keys, as Maelstrom's
+/// `lin-kv` workload does.
+struct LinKv;
+
+impl Workload for LinKv {
+    fn invoke(&mut self, _process: usize, rng: &mut Rng) -> Request {
+        let key = rng.below(LIN_KV_KEYS);
+        match rng.below(3) {
+            0 => Request::new(
+                "read",
+                json!({"type": "read", "key": key}),
+                json!([key, null]),
+            ),
+            1 => {
+                let value = rng.below(LIN_KV_VALUES);
+                Request::new(
+                    "write",
+                    json!({"type": "write", "key": key, "value": value}),
+                    json!([key, value]),
+                )
+            }
+            _ => {
+                let (from, to) = (rng.below(LIN_KV_VALUES), rng.below(LIN_KV_VALUES));
+                Request::new(
+                    "cas",
+                    json!({"type": "cas", "key": key, "from": from, "to": to}),
+                    json!([key, [from, to]]),
+                )
+            }
+        }
+    }
+
+    fn complete(&mut self, _process: usize, request: &Request, reply: &Value) -> Value {
+        match request.f {
+            "read" => json!([request.value[0], reply["value"]]),
+            _ => request.value.clone(),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use crate::{Error, Message, Output, RetryPolicy};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The node id of Maelstrom's linearizable timestamp oracle.
+pub const LIN_TSO: &str = "lin-tso";
+
+#[derive(Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum TsoRequest {
+    Ts,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum TsoResponse {
+    TsOk { ts: u64 },
+}
+
+/// Client for the `lin-tso` service, which hands out strictly increasing
+/// timestamps across all nodes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TsoClient {
+    timeout: Option<Duration>,
+}
+
+impl TsoClient {
+    pub fn new() -> Self {
+        TsoClient::default()
+    }
+
+    /// Fails requests that got no reply within `timeout`, resending them
+    /// every `timeout / 4` in the meantime. Unlike key/value writes, asking
+    /// for a timestamp twice is harmless.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        TsoClient {
+            timeout: Some(timeout),
+        }
+    }
+
+    /// Fetches a fresh timestamp, greater than every timestamp handed out
+    /// before this request was sent.
+    pub fn ts<N, F>(&self, output: &mut Output<N>, callback: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&mut N, Result<u64, Error>, &mut Output<N>) -> anyhow::Result<()> + 'static,
+    {
+        let callback = move |node: &mut N,
+                             reply: Result<Message<TsoResponse>, Error>,
+                             output: &mut Output<N>| {
+            let ts = reply.map(|reply| match reply.body.payload {
+                TsoResponse::TsOk { ts } => ts,
+            });
+            callback(node, ts, output)
+        };
+        match self.timeout {
+            Some(timeout) => output.rpc_with_deadline(
+                LIN_TSO,
+                TsoRequest::Ts,
+                timeout,
+                RetryPolicy::fixed(timeout / 4),
+                callback,
+            ),
+            None => output.rpc(LIN_TSO, TsoRequest::Ts, callback),
+        }?;
+        Ok(())
+    }
+}